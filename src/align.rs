@@ -0,0 +1,337 @@
+use std::{io::{BufRead, Read}, path::Path};
+
+use crate::open_reader;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlignFormat {
+    Sam,
+    Bam,
+    Paf,
+}
+
+/// One alignment line/record, reduced to the fields the tools here use.
+#[derive(Default, Clone, Debug)]
+pub struct Alignment {
+    pub read_id: String,
+    /// None for unmapped records
+    pub target: Option<String>,
+    /// not secondary or supplementary
+    pub primary: bool,
+    pub mapq: u8,
+    /// read length including clipped bases
+    pub query_len: u64,
+    /// number of read bases within the alignment
+    pub query_aligned: u64,
+    /// number of residue matches, if the input records them (PAF, or SAM/BAM with NM)
+    pub matches: Option<u64>,
+    /// alignment block length including gaps
+    pub block_len: u64,
+    pub soft_clipped: u64,
+}
+
+const BAM_MAGIC: &[u8] = b"BAM\x01";
+
+const FLAG_UNMAPPED: u16 = 0x4;
+const FLAG_SECONDARY: u16 = 0x100;
+const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+impl Alignment {
+    pub fn is_mapped(&self) -> bool {
+        self.target.is_some()
+    }
+
+    pub fn identity(&self) -> Option<f64> {
+        if self.block_len == 0 { return None; }
+        self.matches.map(|matches| matches as f64 / self.block_len as f64)
+    }
+
+    pub fn aligned_fraction(&self) -> Option<f64> {
+        if self.query_len == 0 { return None; }
+        Some(self.query_aligned as f64 / self.query_len as f64)
+    }
+}
+
+impl AlignFormat {
+    /// Sniffs the format from the (decompressed) start of the file.
+    pub fn detect(fpath: &Path) -> Option<AlignFormat> {
+        let mut reader = open_reader(fpath);
+        let head = reader.fill_buf().expect("could not read alignment file");
+        if head.starts_with(BAM_MAGIC) {
+            return Some(AlignFormat::Bam);
+        }
+
+        let line = reader.lines().next()?.expect("could not read alignment file");
+        if line.starts_with('@') {
+            return Some(AlignFormat::Sam);
+        }
+
+        text_format(&line)
+    }
+}
+
+/// Tells PAF and SAM body lines apart, PAF has the strand where SAM has the MAPQ.
+pub(crate) fn text_format(line: &str) -> Option<AlignFormat> {
+    let fields = line.split('\t').collect::<Vec<&str>>();
+
+    if fields.len() >= 12 && (fields[4] == "+" || fields[4] == "-") {
+        Some(AlignFormat::Paf)
+    } else if fields.len() >= 11 && fields[1].parse::<u16>().is_ok() {
+        Some(AlignFormat::Sam)
+    } else {
+        None
+    }
+}
+
+/// Calls `f` for every record of a SAM, BAM or PAF file (optionally gzipped).
+pub fn read_alignments<F: FnMut(Alignment)>(fpath: &Path, mut f: F) {
+    let format = AlignFormat::detect(fpath).expect("could not detect alignment format (expected SAM, BAM or PAF)");
+    let mut reader = open_reader(fpath);
+
+    match format {
+        AlignFormat::Bam => read_bam(&mut reader, f),
+        AlignFormat::Sam => {
+            for line in reader.lines() {
+                let line = line.expect("could not read alignment file");
+                if line.starts_with('@') || line.is_empty() { continue; }
+                f(parse_sam_line(&line));
+            }
+        }
+        AlignFormat::Paf => {
+            for line in reader.lines() {
+                let line = line.expect("could not read alignment file");
+                if line.is_empty() { continue; }
+                f(parse_paf_line(&line));
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_paf_line(line: &str) -> Alignment {
+    let fields = line.split('\t').collect::<Vec<&str>>();
+    if fields.len() < 12 {
+        panic!("invalid PAF line: {}", line);
+    }
+    let num = |col: usize| fields[col].parse::<u64>().expect("could not parse PAF column");
+
+    let query_len = num(1);
+    let query_start = num(2);
+    let query_end = num(3);
+    if query_start > query_end || query_end > query_len {
+        panic!("invalid PAF line: {}", line);
+    }
+
+    Alignment {
+        read_id: fields[0].into(),
+        target: if fields[5] == "*" { None } else { Some(fields[5].into()) },
+        primary: !fields[12..].contains(&"tp:A:S"),
+        mapq: fields[11].parse::<u8>().expect("could not parse PAF mapq"),
+        query_len,
+        query_aligned: query_end - query_start,
+        matches: Some(num(9)),
+        block_len: num(10),
+        soft_clipped: query_start + (query_len - query_end),
+    }
+}
+
+#[derive(Default)]
+struct CigarStats {
+    query_len: u64,
+    query_aligned: u64,
+    block_len: u64,
+    soft_clipped: u64,
+}
+
+impl CigarStats {
+    fn add(&mut self, op: u8, len: u64) {
+        match op {
+            b'M' | b'=' | b'X' | b'I' => {
+                self.query_len += len;
+                self.query_aligned += len;
+                self.block_len += len;
+            }
+            b'D' => self.block_len += len,
+            b'S' => {
+                self.query_len += len;
+                self.soft_clipped += len;
+            }
+            b'H' => self.query_len += len,
+            _ => {}
+        }
+    }
+}
+
+fn sam_alignment(read_id: String, target: Option<String>, flag: u16, mapq: u8, cigar: CigarStats, nm: Option<u64>) -> Alignment {
+    let mapped = flag & FLAG_UNMAPPED == 0 && target.is_some();
+
+    Alignment {
+        read_id,
+        target: if mapped { target } else { None },
+        primary: flag & (FLAG_SECONDARY | FLAG_SUPPLEMENTARY) == 0,
+        mapq,
+        query_len: cigar.query_len,
+        query_aligned: cigar.query_aligned,
+        matches: nm.map(|nm| cigar.block_len.saturating_sub(nm)),
+        block_len: cigar.block_len,
+        soft_clipped: cigar.soft_clipped,
+    }
+}
+
+pub(crate) fn parse_sam_line(line: &str) -> Alignment {
+    let fields = line.split('\t').collect::<Vec<&str>>();
+    if fields.len() < 11 {
+        panic!("invalid SAM line: {}", line);
+    }
+
+    let flag = fields[1].parse::<u16>().expect("could not parse SAM flag");
+    let target = if fields[2] == "*" { None } else { Some(fields[2].to_string()) };
+    let mapq = fields[4].parse::<u8>().expect("could not parse SAM mapq");
+
+    let mut cigar = CigarStats::default();
+    if fields[5] != "*" {
+        let mut len = 0;
+        for c in fields[5].bytes() {
+            if c.is_ascii_digit() {
+                len = len * 10 + (c - b'0') as u64;
+            } else {
+                cigar.add(c, len);
+                len = 0;
+            }
+        }
+    }
+    // unaligned records carry no cigar, fall back to SEQ
+    if cigar.query_len == 0 && fields[9] != "*" {
+        cigar.query_len = fields[9].len() as u64;
+    }
+
+    let nm = fields[11..].iter()
+        .find_map(|tag| tag.strip_prefix("NM:i:"))
+        .map(|nm| nm.parse::<u64>().expect("could not parse NM tag"));
+
+    sam_alignment(fields[0].into(), target, flag, mapq, cigar, nm)
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Option<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).ok()?;
+
+    Some(i32::from_le_bytes(buf))
+}
+
+fn read_bam<R: Read, F: FnMut(Alignment)>(reader: &mut R, mut f: F) {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).expect("could not read BAM header");
+    if magic != BAM_MAGIC {
+        panic!("invalid BAM file");
+    }
+
+    let l_text = usize::try_from(read_i32(reader).expect("could not read BAM header")).expect("invalid BAM file");
+    reader.read_exact(&mut vec![0u8; l_text]).expect("could not read BAM header");
+
+    let n_ref = read_i32(reader).expect("could not read BAM header");
+    let mut ref_names = Vec::new();
+    for _ in 0..n_ref {
+        let l_name = usize::try_from(read_i32(reader).expect("could not read BAM references")).expect("invalid BAM file");
+        let mut name = vec![0u8; l_name];
+        reader.read_exact(&mut name).expect("could not read BAM references");
+        name.pop();
+        ref_names.push(String::from_utf8(name).expect("invalid BAM reference name"));
+        read_i32(reader).expect("could not read BAM references");
+    }
+
+    let mut n = 0;
+    while let Some(block_size) = read_i32(reader) {
+        let Ok(block_size) = usize::try_from(block_size) else {
+            panic!("invalid BAM record {}: negative block size {}", n, block_size);
+        };
+        let mut block = vec![0u8; block_size];
+        reader.read_exact(&mut block).expect("truncated BAM record");
+        match parse_bam_record(&block, &ref_names) {
+            Ok(alignment) => f(alignment),
+            Err(err) => panic!("invalid BAM record {}: {}", n, err),
+        }
+        n += 1;
+    }
+}
+
+/// Fixed-length part of a BAM record, before the read name.
+const BAM_RECORD_FIXED: usize = 32;
+
+fn parse_bam_record(block: &[u8], ref_names: &[String]) -> Result<Alignment, String> {
+    if block.len() < BAM_RECORD_FIXED {
+        return Err(format!("{} bytes is shorter than the fixed fields", block.len()));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([block[i], block[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([block[i], block[i + 1], block[i + 2], block[i + 3]]);
+
+    let ref_id = u32_at(0) as i32;
+    let l_read_name = block[8] as usize;
+    let mapq = block[9];
+    let n_cigar_op = u16_at(12) as usize;
+    let flag = u16_at(14);
+    let l_seq = u32_at(16) as usize;
+
+    if l_read_name == 0 {
+        return Err("empty read name".into());
+    }
+    let len = BAM_RECORD_FIXED + l_read_name + 4 * n_cigar_op + l_seq.div_ceil(2) + l_seq;
+    if len > block.len() {
+        return Err(format!("fields need {} bytes but the record has {}", len, block.len()));
+    }
+
+    let mut pos = BAM_RECORD_FIXED;
+    let read_id = String::from_utf8_lossy(&block[pos..pos + l_read_name - 1]).into_owned();
+    pos += l_read_name;
+
+    let mut cigar = CigarStats::default();
+    for _ in 0..n_cigar_op {
+        let op = u32_at(pos);
+        let code = b"MIDNSHP=X".get((op & 0xf) as usize).ok_or(format!("invalid cigar op {}", op & 0xf))?;
+        cigar.add(*code, (op >> 4) as u64);
+        pos += 4;
+    }
+    if cigar.query_len == 0 {
+        cigar.query_len = l_seq as u64;
+    }
+    pos += l_seq.div_ceil(2) + l_seq;
+
+    let target = usize::try_from(ref_id).ok().and_then(|ref_id| ref_names.get(ref_id)).cloned();
+    let nm = bam_int_tag(&block[pos..], b"NM");
+
+    Ok(sam_alignment(read_id, target, flag, mapq, cigar, nm))
+}
+
+fn bam_int_tag(mut tags: &[u8], name: &[u8; 2]) -> Option<u64> {
+    let size_of = |t: u8| match t {
+        b'A' | b'c' | b'C' => 1,
+        b's' | b'S' => 2,
+        b'i' | b'I' | b'f' => 4,
+        _ => 0,
+    };
+
+    while tags.len() >= 3 {
+        let t = tags[2];
+        let val = &tags[3..];
+        if &tags[..2] == name {
+            // a truncated value reads as missing
+            return match (t, val) {
+                (b'c', [a, ..]) => Some(*a as i8 as u64),
+                (b'C', [a, ..]) => Some(*a as u64),
+                (b's', [a, b, ..]) => Some(i16::from_le_bytes([*a, *b]) as u64),
+                (b'S', [a, b, ..]) => Some(u16::from_le_bytes([*a, *b]) as u64),
+                (b'i', [a, b, c, d, ..]) => Some(i32::from_le_bytes([*a, *b, *c, *d]) as u64),
+                (b'I', [a, b, c, d, ..]) => Some(u32::from_le_bytes([*a, *b, *c, *d]) as u64),
+                _ => None,
+            };
+        }
+
+        let len = match (t, val) {
+            (b'Z' | b'H', _) => val.iter().position(|c| *c == 0)? + 1,
+            (b'B', [sub, a, b, c, d, ..]) => 5 + u32::from_le_bytes([*a, *b, *c, *d]) as usize * size_of(*sub),
+            (b'B', _) => return None,
+            _ => size_of(t),
+        };
+        tags = val.get(len..)?;
+    }
+
+    None
+}
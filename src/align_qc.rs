@@ -0,0 +1,169 @@
+//! Alignment quality of selected reads against controls, to see whether reads taken
+//! before pore death map less often or less well.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::{fisher_exact, mann_whitney, read_alignments, Alignment, MetricComparison, MetricSummary, ReadId};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadGroup {
+    Selected,
+    Control,
+}
+
+/// Alignment metrics of one read, from its best primary alignment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadAlignQc {
+    pub read_id: String,
+    pub group: ReadGroup,
+    /// alignment records of the read, 0 if it is missing from the alignments
+    pub records: usize,
+    /// None if the read has no mapped primary alignment
+    pub target: Option<String>,
+    pub mapq: u8,
+    pub query_len: u64,
+    /// read bases within the alignment
+    pub query_aligned: u64,
+    pub identity: Option<f64>,
+    pub aligned_fraction: Option<f64>,
+    /// soft-clipped bases over the read length
+    pub clipped_fraction: Option<f64>,
+}
+
+pub const ALIGN_QC_TSV_HEADER: &str = "read_id\tgroup\trecords\ttarget\tmapq\tquery_len\tidentity\taligned_fraction\tclipped_fraction";
+
+impl ReadGroup {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReadGroup::Selected => "selected",
+            ReadGroup::Control => "control",
+        }
+    }
+}
+
+fn fmt_opt(val: Option<f64>) -> String {
+    val.map_or_else(|| "NA".to_string(), |val| format!("{:.4}", val))
+}
+
+impl ReadAlignQc {
+    pub fn is_mapped(&self) -> bool {
+        self.target.is_some()
+    }
+
+    pub fn tsv_row(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.read_id,
+            self.group.name(),
+            self.records,
+            self.target.as_deref().unwrap_or("*"),
+            self.mapq,
+            self.query_len,
+            fmt_opt(self.identity),
+            fmt_opt(self.aligned_fraction),
+            fmt_opt(self.clipped_fraction),
+        )
+    }
+}
+
+/// Ranks primary mapped alignments by MAPQ, then by read bases aligned.
+fn is_better(alignment: &Alignment, qc: &ReadAlignQc) -> bool {
+    match &qc.target {
+        None => true,
+        Some(_) => (alignment.mapq, alignment.query_aligned) > (qc.mapq, qc.query_aligned),
+    }
+}
+
+/// Metrics of every read in `reads`, in their order. Reads in both groups are kept
+/// once per group.
+pub fn align_qc(align_fpath: &Path, reads: &[(String, ReadGroup)]) -> Vec<ReadAlignQc> {
+    let mut ret = reads.iter()
+        .map(|(read_id, group)| ReadAlignQc {
+            read_id: read_id.clone(),
+            group: *group,
+            records: 0,
+            target: None,
+            mapq: 0,
+            query_len: 0,
+            query_aligned: 0,
+            identity: None,
+            aligned_fraction: None,
+            clipped_fraction: None,
+        })
+        .collect::<Vec<ReadAlignQc>>();
+
+    let mut idx = HashMap::<ReadId, Vec<usize>>::new();
+    for (i, (read_id, _)) in reads.iter().enumerate() {
+        idx.entry(ReadId::from(read_id.as_str())).or_default().push(i);
+    }
+
+    read_alignments(align_fpath, |alignment| {
+        let Some(idxs) = idx.get(&ReadId::from(alignment.read_id.as_str())) else { return; };
+
+        for i in idxs.iter() {
+            let qc = &mut ret[*i];
+            qc.records += 1;
+            qc.query_len = qc.query_len.max(alignment.query_len);
+            if !alignment.is_mapped() || !alignment.primary || !is_better(&alignment, qc) {
+                continue;
+            }
+
+            qc.target = alignment.target.clone();
+            qc.mapq = alignment.mapq;
+            qc.query_len = alignment.query_len;
+            qc.query_aligned = alignment.query_aligned;
+            qc.identity = alignment.identity();
+            qc.aligned_fraction = alignment.aligned_fraction();
+            qc.clipped_fraction = (alignment.query_len > 0).then(|| alignment.soft_clipped as f64 / alignment.query_len as f64);
+        }
+    });
+
+    ret
+}
+
+type Metric = fn(&ReadAlignQc) -> Option<f64>;
+
+/// Metrics compared over mapped reads.
+const METRICS: [(&str, Metric); 4] = [
+    ("mapq", |qc| Some(qc.mapq as f64)),
+    ("identity", |qc| qc.identity),
+    ("aligned_fraction", |qc| qc.aligned_fraction),
+    ("clipped_fraction", |qc| qc.clipped_fraction),
+];
+
+/// Compares the mapping rate of the groups, then MAPQ, identity, aligned and clipped
+/// fractions of their mapped reads.
+pub fn compare_align_qc(reads: &[ReadAlignQc]) -> Vec<MetricComparison> {
+    let mapped = |group: ReadGroup| {
+        let mapped = group_values(reads, group, |qc| Some(qc.is_mapped() as u8 as f64));
+        let count = mapped.iter().sum::<f64>() as u64;
+        (mapped, count)
+    };
+
+    let ((selected, selected_mapped), (control, control_mapped)) = (mapped(ReadGroup::Selected), mapped(ReadGroup::Control));
+    let mut ret = vec![MetricComparison {
+        metric: "mapped",
+        selected: MetricSummary::rate(&selected),
+        control: MetricSummary::rate(&control),
+        p_value: fisher_exact(selected_mapped, selected.len() as u64 - selected_mapped, control_mapped, control.len() as u64 - control_mapped),
+    }];
+
+    for (metric, value) in METRICS {
+        let mapped_value = |qc: &ReadAlignQc| if qc.is_mapped() { value(qc) } else { None };
+        let selected = group_values(reads, ReadGroup::Selected, mapped_value);
+        let control = group_values(reads, ReadGroup::Control, mapped_value);
+
+        ret.push(MetricComparison {
+            metric,
+            selected: MetricSummary::of(&selected),
+            control: MetricSummary::of(&control),
+            p_value: mann_whitney(&selected, &control).p_value,
+        });
+    }
+
+    ret
+}
+
+fn group_values<F: Fn(&ReadAlignQc) -> Option<f64>>(reads: &[ReadAlignQc], group: ReadGroup, value: F) -> Vec<f64> {
+    reads.iter().filter(|qc| qc.group == group).filter_map(value).collect()
+}
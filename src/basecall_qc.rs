@@ -0,0 +1,156 @@
+//! Basecall quality of selected reads against the others, joined from the basecaller's
+//! sequencing_summary.txt.
+
+use std::{collections::HashSet, fmt::Write, io::BufRead, path::Path};
+
+use crate::{fisher_exact, json_num, json_str, mann_whitney, open_reader, MetricComparison, MetricSummary, ReadId};
+
+/// The sequencing summary columns compared, `None` where a column is missing or empty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SummaryRead {
+    pub read_id: ReadId,
+    pub passes_filtering: Option<bool>,
+    pub length: Option<u64>,
+    pub qscore: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BasecallReport {
+    pub selected: usize,
+    pub control: usize,
+    /// selected and control read_ids missing from the sequencing summary
+    pub missing: usize,
+    /// q-score, pass rate and length
+    pub comparisons: Vec<MetricComparison>,
+}
+
+/// Metric values of one group, `passes` as 0 or 1.
+#[derive(Default)]
+struct GroupValues {
+    reads: usize,
+    qscore: Vec<f64>,
+    passes: Vec<f64>,
+    length: Vec<f64>,
+}
+
+fn parse_bool(val: &str) -> Option<bool> {
+    match val.to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Calls `f` on every row of a sequencing summary with its `read_id`, `passes_filtering`,
+/// `sequence_length_template` and `mean_qscore_template`. Only `read_id` is required.
+pub fn read_seq_summary<F: FnMut(SummaryRead)>(summary_fpath: &Path, mut f: F) {
+    let mut lines = open_reader(summary_fpath).lines();
+    let header = lines.next().expect("empty sequencing summary").expect("could not read sequencing summary");
+    let cols = header.split('\t').collect::<Vec<&str>>();
+    let col = |name: &str| cols.iter().position(|col| *col == name);
+    let read_id_col = col("read_id").expect("sequencing summary has no read_id column");
+    let (pass_col, length_col, qscore_col) = (col("passes_filtering"), col("sequence_length_template"), col("mean_qscore_template"));
+
+    for line in lines {
+        let line = line.expect("could not read sequencing summary");
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let field = |col: Option<usize>| col.and_then(|col| fields.get(col)).copied();
+        let Some(read_id) = field(Some(read_id_col)) else { continue; };
+
+        f(SummaryRead {
+            read_id: ReadId::from(read_id),
+            passes_filtering: field(pass_col).and_then(parse_bool),
+            length: field(length_col).and_then(|val| val.parse().ok()),
+            qscore: field(qscore_col).and_then(|val| val.parse().ok()),
+        });
+    }
+}
+
+/// Compares the selected reads against `controls`, or against every other read in the
+/// summary without them. Reads are counted once, from their first row.
+pub fn compare_basecalls(summary_fpath: &Path, selected: &HashSet<ReadId>, controls: Option<&HashSet<ReadId>>) -> BasecallReport {
+    let mut found = HashSet::<ReadId>::new();
+    let (mut selected_values, mut control_values) = (GroupValues::default(), GroupValues::default());
+
+    read_seq_summary(summary_fpath, |read| {
+        let values = if selected.contains(&read.read_id) {
+            &mut selected_values
+        } else if controls.is_none_or(|controls| controls.contains(&read.read_id)) {
+            &mut control_values
+        } else {
+            return;
+        };
+        // concatenated summaries repeat reads, only their first row counts
+        if !found.insert(read.read_id) {
+            return;
+        }
+
+        values.reads += 1;
+        values.qscore.extend(read.qscore);
+        values.passes.extend(read.passes_filtering.map(|passes| passes as u8 as f64));
+        values.length.extend(read.length.map(|length| length as f64));
+    });
+
+    let missing = selected.iter().filter(|read_id| !found.contains(*read_id)).count()
+        + controls.map_or(0, |controls| controls.iter().filter(|read_id| !found.contains(*read_id) && !selected.contains(*read_id)).count());
+
+    let mut ret = BasecallReport {
+        selected: selected_values.reads,
+        control: control_values.reads,
+        missing,
+        comparisons: Vec::new(),
+    };
+
+    let (selected, control) = (&selected_values.qscore, &control_values.qscore);
+    ret.comparisons.push(MetricComparison {
+        metric: "qscore",
+        selected: MetricSummary::of(selected),
+        control: MetricSummary::of(control),
+        p_value: mann_whitney(selected, control).p_value,
+    });
+
+    let (selected, control) = (&selected_values.passes, &control_values.passes);
+    let (selected_pass, control_pass) = (selected.iter().sum::<f64>() as u64, control.iter().sum::<f64>() as u64);
+    ret.comparisons.push(MetricComparison {
+        metric: "passes_filtering",
+        selected: MetricSummary::rate(selected),
+        control: MetricSummary::rate(control),
+        p_value: fisher_exact(selected_pass, selected.len() as u64 - selected_pass, control_pass, control.len() as u64 - control_pass),
+    });
+
+    let (selected, control) = (&selected_values.length, &control_values.length);
+    ret.comparisons.push(MetricComparison {
+        metric: "length",
+        selected: MetricSummary::of(selected),
+        control: MetricSummary::of(control),
+        p_value: mann_whitney(selected, control).p_value,
+    });
+
+    ret
+}
+
+fn summary_json(summary: &MetricSummary) -> String {
+    format!(
+        "{{\"n\":{},\"mean\":{},\"median\":{},\"q25\":{},\"q75\":{}}}",
+        summary.n, json_num(summary.mean), json_num(summary.median), json_num(summary.q25), json_num(summary.q75),
+    )
+}
+
+impl BasecallReport {
+    pub fn to_json(&self) -> String {
+        let mut ret = format!("{{\"selected\":{},\"control\":{},\"missing\":{},\"metrics\":[", self.selected, self.control, self.missing);
+        for (i, comparison) in self.comparisons.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            write!(
+                ret,
+                "{{\"metric\":{},\"selected\":{},\"control\":{},\"p_value\":{}}}",
+                json_str(comparison.metric), summary_json(&comparison.selected), summary_json(&comparison.control), json_num(comparison.p_value),
+            ).unwrap();
+        }
+        ret.push_str("]}\n");
+
+        ret
+    }
+}
@@ -0,0 +1,348 @@
+//! Metadata-only BLOW5 reading. Building read timestamps needs the read_id, sampling
+//! rate and a few aux fields, so records are parsed here without decoding the raw
+//! signal. Layouts that aren't handled (SLOW5 text, zstd records, unknown signal
+//! compression) return `None` so the caller can fall back to the slow5 library.
+
+use std::{fs::File, io::{BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}, thread};
+
+use flate2::read::ZlibDecoder;
+
+use crate::{log_warn, Progress, ReadMeta};
+
+const BLOW5_MAGIC: &[u8] = b"BLOW5\x01";
+const BLOW5_EOF: &[u8] = b"5WOLB";
+const IDX_MAGIC: &[u8] = b"SLOW5IDX\x01";
+const IDX_EOF: &[u8] = b"XDI5WOLS";
+const HEADER_SIZE_OFFSET: u64 = 64;
+
+const RECORD_PRESS_NONE: u8 = 0;
+const RECORD_PRESS_ZLIB: u8 = 1;
+const SIGNAL_PRESS_NONE: u8 = 0;
+const SIGNAL_PRESS_SVB_ZD: u8 = 1;
+
+#[derive(Clone, Copy)]
+enum AuxType {
+    Fixed(usize),
+    /// `<type>*` arrays, stored as a u64 length followed by the elements
+    Array(usize),
+}
+
+#[derive(Clone, Copy, Default)]
+struct AuxCols {
+    channel: Option<usize>,
+    mux: Option<usize>,
+    start_time: Option<usize>,
+}
+
+struct Layout {
+    record_press: u8,
+    signal_press: u8,
+    aux_types: Vec<AuxType>,
+    aux_cols: AuxCols,
+    records_start: u64,
+}
+
+fn aux_type(type_name: &str) -> Option<AuxType> {
+    let (elem, is_array) = match type_name.strip_suffix('*') {
+        Some(elem) => (elem, true),
+        None => (type_name, false),
+    };
+    let size = match elem {
+        "int8_t" | "uint8_t" | "char" => 1,
+        "int16_t" | "uint16_t" => 2,
+        "int32_t" | "uint32_t" | "float" => 4,
+        "int64_t" | "uint64_t" | "double" => 8,
+        _ if elem.starts_with("enum{") => 1,
+        _ => return None,
+    };
+
+    Some(if is_array { AuxType::Array(size) } else { AuxType::Fixed(size) })
+}
+
+fn read_layout(file: &mut File) -> Option<Layout> {
+    let mut fixed = [0u8; HEADER_SIZE_OFFSET as usize + 4];
+    file.read_exact(&mut fixed).ok()?;
+    if !fixed.starts_with(BLOW5_MAGIC) {
+        return None;
+    }
+
+    let record_press = fixed[9];
+    let signal_press = fixed[14];
+    if record_press != RECORD_PRESS_NONE && record_press != RECORD_PRESS_ZLIB {
+        return None;
+    }
+    if signal_press != SIGNAL_PRESS_NONE && signal_press != SIGNAL_PRESS_SVB_ZD {
+        return None;
+    }
+
+    let header_size = u32::from_le_bytes(fixed[64..68].try_into().unwrap()) as usize;
+    let mut header = vec![0u8; header_size];
+    file.read_exact(&mut header).ok()?;
+    let header = String::from_utf8(header).ok()?;
+
+    let types = header.lines().find(|line| line.starts_with("#char*"))?.split('\t').collect::<Vec<&str>>();
+    let names = header.lines().find(|line| line.starts_with("#read_id"))?.split('\t').collect::<Vec<&str>>();
+
+    // read_id, read_group, digitisation, offset, range, sampling_rate, len_raw_signal, raw_signal
+    let n_primary = 8;
+    if types.len() != names.len() || names.len() < n_primary {
+        return None;
+    }
+
+    let mut aux_cols = AuxCols::default();
+    let mut aux_types = Vec::new();
+    for (i, (name, type_name)) in names[n_primary..].iter().zip(types[n_primary..].iter()).enumerate() {
+        aux_types.push(aux_type(type_name)?);
+        match *name {
+            "channel_number" => aux_cols.channel = Some(i),
+            "start_mux" => aux_cols.mux = Some(i),
+            "start_time" => aux_cols.start_time = Some(i),
+            _ => {}
+        }
+    }
+
+    Some(Layout {
+        record_press,
+        signal_press,
+        aux_types,
+        aux_cols,
+        records_start: HEADER_SIZE_OFFSET + 4 + header_size as u64,
+    })
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let ret = self.pos.checked_add(n)
+            .and_then(|end| self.buf.get(self.pos..end))
+            .ok_or_else(|| format!("record ends after {} bytes, {} more needed at {}", self.buf.len(), n, self.pos))?;
+        self.pos += n;
+        Ok(ret)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+impl Layout {
+    /// Fails on corrupt records, which callers skip as the slow5 library path does.
+    fn parse_record(&self, raw: &[u8], scratch: &mut Vec<u8>) -> Result<ReadMeta, String> {
+        let buf = match self.record_press {
+            RECORD_PRESS_ZLIB => {
+                scratch.clear();
+                ZlibDecoder::new(raw).read_to_end(scratch).map_err(|err| format!("could not inflate record: {}", err))?;
+                &scratch[..]
+            }
+            _ => raw,
+        };
+        let mut cur = Cursor { buf, pos: 0 };
+
+        let read_id_len = u16::from_le_bytes(cur.take(2)?.try_into().unwrap()) as usize;
+        let read_id = std::str::from_utf8(cur.take(read_id_len)?).map_err(|_| "read_id is not utf-8".to_string())?.into();
+        // read_group, digitisation, offset, range
+        cur.take(4 + 8 * 3)?;
+        let sampling_rate = cur.f64()?;
+
+        // with signal compression the stored length is in bytes, the sample count
+        // leads the compressed block
+        let len_raw_signal = cur.u64()?;
+        let len_signal = match self.signal_press {
+            SIGNAL_PRESS_SVB_ZD => {
+                let signal = cur.take(len_raw_signal as usize)?;
+                let count = signal.get(..4).ok_or("compressed signal has no sample count")?;
+                u32::from_le_bytes(count.try_into().unwrap()) as u64
+            }
+            _ => {
+                cur.take((len_raw_signal as usize).saturating_mul(2))?;
+                len_raw_signal
+            }
+        };
+
+        let mut channel = None;
+        let mut mux = None;
+        let mut samples_start = None;
+        for (i, aux_type) in self.aux_types.iter().enumerate() {
+            let val = match aux_type {
+                AuxType::Fixed(size) => cur.take(*size)?,
+                AuxType::Array(size) => {
+                    let len = cur.u64()? as usize;
+                    cur.take(len.saturating_mul(*size))?
+                }
+            };
+
+            if Some(i) == self.aux_cols.channel {
+                let val = std::str::from_utf8(val).ok().and_then(|val| val.parse::<u32>().ok());
+                channel = Some(val.ok_or("could not parse channel_number as u32")?);
+            } else if Some(i) == self.aux_cols.mux {
+                mux = Some(*val.first().ok_or("empty aux_field `start_mux`")?);
+            } else if Some(i) == self.aux_cols.start_time {
+                let val = val.try_into().map_err(|_| "could not load aux_field `start_time`")?;
+                samples_start = Some(u64::from_le_bytes(val));
+            }
+        }
+
+        Ok(ReadMeta {
+            read_id,
+            sampling_rate,
+            len_signal,
+            channel: channel.ok_or("could not load aux_field `channel_number`")?,
+            mux: mux.ok_or("could not load aux_field `start_mux`")?,
+            samples_start: samples_start.ok_or("could not load aux_field `start_time`")?,
+        })
+    }
+
+    fn has_timestamp_fields(&self) -> bool {
+        self.aux_cols.channel.is_some() && self.aux_cols.mux.is_some() && self.aux_cols.start_time.is_some()
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Option<[u8; 8]> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+/// (offset, size) of every record, from the `.idx` next to the blow5.
+fn read_index(slow5_fpath: &Path) -> Option<Vec<(u64, u64)>> {
+    let mut idx_fpath = PathBuf::from(slow5_fpath).into_os_string();
+    idx_fpath.push(".idx");
+
+    let mut idx = BufReader::new(File::open(idx_fpath).ok()?);
+    let mut fixed = [0u8; HEADER_SIZE_OFFSET as usize];
+    idx.read_exact(&mut fixed).ok()?;
+    if !fixed.starts_with(IDX_MAGIC) {
+        return None;
+    }
+
+    let mut ret = Vec::new();
+    loop {
+        let mut read_id_len = [0u8; 2];
+        idx.read_exact(&mut read_id_len).ok()?;
+        if read_id_len == IDX_EOF[..2] {
+            break;
+        }
+        idx.seek_relative(u16::from_le_bytes(read_id_len) as i64).ok()?;
+
+        let offset = u64::from_le_bytes(read_u64(&mut idx)?);
+        let size = u64::from_le_bytes(read_u64(&mut idx)?);
+        ret.push((offset, size));
+    }
+
+    Some(ret)
+}
+
+fn for_each_sequential<F: FnMut(ReadMeta)>(mut file: File, layout: &Layout, mut f: F) {
+    let mut raw = Vec::new();
+    let mut scratch = Vec::new();
+
+    file.seek(SeekFrom::Start(layout.records_start)).expect("could not seek in blow5");
+    let mut reader = BufReader::new(file);
+
+    while let Some(size) = read_u64(&mut reader) {
+        if size.starts_with(BLOW5_EOF) {
+            break;
+        }
+        let size = u64::from_le_bytes(size);
+        raw.clear();
+        if (&mut reader).take(size).read_to_end(&mut raw).is_err() || (raw.len() as u64) < size {
+            // nothing after a truncated record can be found without an index
+            log_warn!("truncated record of {} bytes, stopping", size);
+            break;
+        }
+
+        match layout.parse_record(&raw, &mut scratch) {
+            Ok(meta) => f(meta),
+            Err(err) => log_warn!("error reading record {:?}, skipping...", err),
+        }
+    }
+}
+
+fn scan_indexed(slow5_fpath: &Path, layout: &Layout, index: &[(u64, u64)], threads: usize, progress: &Progress) -> Vec<ReadMeta> {
+    let chunk_size = index.len().div_ceil(threads).max(1);
+
+    thread::scope(|scope| {
+        let workers = index.chunks(chunk_size)
+            .map(|entries| scope.spawn(move || {
+                let mut file = File::open(slow5_fpath).expect("could not open slow5");
+                let mut ret = Vec::with_capacity(entries.len());
+                let mut raw = Vec::new();
+                let mut scratch = Vec::new();
+
+                for (offset, size) in entries.iter() {
+                    progress.inc();
+                    // index sizes include the u64 size prefix
+                    let Some(size) = size.checked_sub(8) else {
+                        log_warn!("record at {} has an invalid size {} in the index, skipping...", offset, size);
+                        continue;
+                    };
+                    raw.clear();
+                    file.seek(SeekFrom::Start(offset + 8)).expect("could not seek in blow5");
+                    if (&mut file).take(size).read_to_end(&mut raw).is_err() || (raw.len() as u64) < size {
+                        log_warn!("truncated record at {}, skipping...", offset);
+                        continue;
+                    }
+
+                    match layout.parse_record(&raw, &mut scratch) {
+                        Ok(meta) => ret.push(meta),
+                        Err(err) => log_warn!("error reading record {:?}, skipping...", err),
+                    }
+                }
+
+                ret
+            }))
+            .collect::<Vec<_>>();
+
+        workers.into_iter()
+            .flat_map(|worker| worker.join().expect("blow5 scanning thread panicked"))
+            .collect()
+    })
+}
+
+/// Reads the metadata of every record, or `None` if the file isn't a BLOW5 this
+/// module can parse. Several threads are only used when an index is present, with a
+/// warning otherwise.
+pub(crate) fn scan_read_meta(slow5_fpath: &Path, threads: usize, progress: &Progress) -> Option<Vec<ReadMeta>> {
+    let mut file = File::open(slow5_fpath).ok()?;
+    let layout = read_layout(&mut file)?;
+    if !layout.has_timestamp_fields() {
+        return None;
+    }
+
+    if threads > 1 {
+        match read_index(slow5_fpath) {
+            Some(index) => return Some(scan_indexed(slow5_fpath, &layout, &index, threads, progress)),
+            None => log_warn!("no index next to {}, scanning it on one thread", slow5_fpath.display()),
+        }
+    }
+
+    let mut ret = Vec::new();
+    for_each_sequential(file, &layout, |meta| {
+        ret.push(meta);
+        progress.inc();
+    });
+
+    Some(ret)
+}
+
+/// Streams the metadata of every record into `f` in file order. Returns false
+/// without calling `f` if the file isn't a BLOW5 this module can parse.
+pub(crate) fn for_each_read_meta<F: FnMut(ReadMeta)>(slow5_fpath: &Path, f: F) -> bool {
+    let Ok(mut file) = File::open(slow5_fpath) else { return false; };
+    let Some(layout) = read_layout(&mut file) else { return false; };
+    if !layout.has_timestamp_fields() {
+        return false;
+    }
+
+    for_each_sequential(file, &layout, f);
+
+    true
+}
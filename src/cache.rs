@@ -0,0 +1,211 @@
+//! Binary sidecar caching the per-read metadata of a slow5 file, so repeated runs
+//! on the same file skip the scan. It is keyed by the slow5 file's size and mtime
+//! and ignored once either changes.
+
+use std::{fs::{self, File, OpenOptions}, io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, process, sync::atomic::{AtomicUsize, Ordering as AtomicOrdering}, time::UNIX_EPOCH};
+
+use crate::{log_warn, ReadMeta};
+
+const CACHE_MAGIC: &[u8] = b"BRTSIDX\x01";
+const CACHE_EXT: &str = ".timestamps";
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(PartialEq, Eq)]
+struct Key {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+}
+
+fn slow5_key(slow5_fpath: &Path) -> io::Result<Key> {
+    let metadata = fs::metadata(slow5_fpath)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    Ok(Key {
+        size: metadata.len(),
+        mtime_secs: mtime.as_secs(),
+        mtime_nanos: mtime.subsec_nanos(),
+    })
+}
+
+pub fn cache_path(slow5_fpath: &Path) -> PathBuf {
+    let mut ret = slow5_fpath.as_os_str().to_owned();
+    ret.push(CACHE_EXT);
+    ret.into()
+}
+
+fn read_bytes<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Checks the magic and key, leaving `reader` at the read count.
+fn open_cache(cache_fpath: &Path, key: &Key) -> io::Result<Option<BufReader<File>>> {
+    let mut reader = BufReader::new(File::open(cache_fpath)?);
+
+    if read_bytes::<8, _>(&mut reader)? != CACHE_MAGIC {
+        return Ok(None);
+    }
+    let cached_key = Key {
+        size: u64::from_le_bytes(read_bytes(&mut reader)?),
+        mtime_secs: u64::from_le_bytes(read_bytes(&mut reader)?),
+        mtime_nanos: u32::from_le_bytes(read_bytes(&mut reader)?),
+    };
+    if cached_key != *key {
+        return Ok(None);
+    }
+
+    Ok(Some(reader))
+}
+
+fn read_entry<R: Read>(reader: &mut R) -> io::Result<ReadMeta> {
+    let read_id_len = u16::from_le_bytes(read_bytes(reader)?) as usize;
+    let mut read_id = vec![0u8; read_id_len];
+    reader.read_exact(&mut read_id)?;
+
+    Ok(ReadMeta {
+        read_id: String::from_utf8(read_id).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?.into(),
+        channel: u32::from_le_bytes(read_bytes(reader)?),
+        mux: read_bytes::<1, _>(reader)?[0],
+        samples_start: u64::from_le_bytes(read_bytes(reader)?),
+        sampling_rate: f64::from_le_bytes(read_bytes(reader)?),
+        len_signal: u64::from_le_bytes(read_bytes(reader)?),
+    })
+}
+
+/// Passes `n_reads` entries to `f`, returning whether they could all be read and
+/// nothing follows them.
+fn entries_intact<R: Read, F: FnMut(ReadMeta)>(reader: &mut R, n_reads: u64, mut f: F) -> bool {
+    (0..n_reads).all(|_| read_entry(reader).map(&mut f).is_ok()) && reader.read(&mut [0u8; 1]).is_ok_and(|n| n == 0)
+}
+
+/// Returns the cached metadata, or `None` if there is no cache or it is stale or unreadable.
+pub fn read_meta_cache(cache_fpath: &Path, slow5_fpath: &Path) -> Option<Vec<ReadMeta>> {
+    let key = slow5_key(slow5_fpath).ok()?;
+    let mut reader = open_cache(cache_fpath, &key).ok()??;
+    let n_reads = u64::from_le_bytes(read_bytes(&mut reader).ok()?);
+
+    let mut ret = Vec::new();
+    if !entries_intact(&mut reader, n_reads, |meta| ret.push(meta)) {
+        log_warn!("timestamp cache {} is corrupt, rebuilding it", cache_fpath.display());
+        return None;
+    }
+
+    Some(ret)
+}
+
+/// Streams the cached metadata into `f` without collecting it. Returns false without
+/// calling `f` if there is no valid cache. The entries are checked in a first pass,
+/// so a truncated or corrupt cache counts as missing rather than failing half way.
+pub(crate) fn for_each_cached_meta<F: FnMut(ReadMeta)>(cache_fpath: &Path, slow5_fpath: &Path, mut f: F) -> bool {
+    let Ok(key) = slow5_key(slow5_fpath) else { return false; };
+    let open = || -> Option<(BufReader<File>, u64)> {
+        let mut reader = open_cache(cache_fpath, &key).ok()??;
+        let n_reads = read_bytes(&mut reader).map(u64::from_le_bytes).ok()?;
+        Some((reader, n_reads))
+    };
+
+    let Some((mut reader, n_reads)) = open() else { return false; };
+    if !entries_intact(&mut reader, n_reads, |_| {}) {
+        log_warn!("timestamp cache {} is corrupt, rebuilding it", cache_fpath.display());
+        return false;
+    }
+
+    let Some((mut reader, n_reads)) = open() else { return false; };
+    for _ in 0..n_reads {
+        match read_entry(&mut reader) {
+            Ok(meta) => f(meta),
+            // only if the file changed since the check
+            Err(err) => panic!("timestamp cache {} changed while reading it: {}", cache_fpath.display(), err),
+        }
+    }
+
+    true
+}
+
+/// Writes a cache one read at a time, through a temporary file so readers never
+/// see a partial cache. The temporary file is removed if writing fails or the
+/// writer is dropped before `finish`.
+pub(crate) struct CacheWriter {
+    /// `None` once finished
+    writer: Option<BufWriter<File>>,
+    tmp_fpath: PathBuf,
+    cache_fpath: PathBuf,
+    n_reads: u64,
+}
+
+const N_READS_OFFSET: u64 = 8 + 8 + 8 + 4;
+
+impl CacheWriter {
+    pub(crate) fn create(cache_fpath: &Path, slow5_fpath: &Path) -> io::Result<CacheWriter> {
+        let key = slow5_key(slow5_fpath)?;
+
+        // unique per writer, so runs on the same slow5 don't write into each other's file
+        let mut tmp_fpath = cache_fpath.as_os_str().to_owned();
+        tmp_fpath.push(format!(".{}_{}.tmp", process::id(), TMP_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)));
+        let tmp_fpath = PathBuf::from(tmp_fpath);
+
+        let writer = BufWriter::new(OpenOptions::new().create_new(true).write(true).open(&tmp_fpath)?);
+        let mut ret = CacheWriter { writer: Some(writer), tmp_fpath, cache_fpath: cache_fpath.into(), n_reads: 0 };
+        let writer = ret.writer.as_mut().unwrap();
+        writer.write_all(CACHE_MAGIC)?;
+        writer.write_all(&key.size.to_le_bytes())?;
+        writer.write_all(&key.mtime_secs.to_le_bytes())?;
+        writer.write_all(&key.mtime_nanos.to_le_bytes())?;
+        // patched in `finish`
+        writer.write_all(&0u64.to_le_bytes())?;
+
+        Ok(ret)
+    }
+
+    pub(crate) fn push(&mut self, meta: &ReadMeta) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("cache writer already finished");
+        let read_id = meta.read_id.to_string();
+        writer.write_all(&(read_id.len() as u16).to_le_bytes())?;
+        writer.write_all(read_id.as_bytes())?;
+        writer.write_all(&meta.channel.to_le_bytes())?;
+        writer.write_all(&[meta.mux])?;
+        writer.write_all(&meta.samples_start.to_le_bytes())?;
+        writer.write_all(&meta.sampling_rate.to_le_bytes())?;
+        writer.write_all(&meta.len_signal.to_le_bytes())?;
+        self.n_reads += 1;
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        let writer = self.writer.take().expect("cache writer already finished");
+        let n_reads = self.n_reads;
+        let ret = (|| {
+            let mut file = writer.into_inner().map_err(|err| err.into_error())?;
+            file.seek(SeekFrom::Start(N_READS_OFFSET))?;
+            file.write_all(&n_reads.to_le_bytes())?;
+            file.sync_all()?;
+            fs::rename(&self.tmp_fpath, &self.cache_fpath)
+        })();
+
+        if ret.is_err() {
+            let _ = fs::remove_file(&self.tmp_fpath);
+        }
+        ret
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_fpath);
+        }
+    }
+}
+
+pub fn write_meta_cache(cache_fpath: &Path, slow5_fpath: &Path, read_metas: &[ReadMeta]) -> io::Result<()> {
+    let mut writer = CacheWriter::create(cache_fpath, slow5_fpath)?;
+    for meta in read_metas.iter() {
+        writer.push(meta)?;
+    }
+
+    writer.finish()
+}
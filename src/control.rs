@@ -0,0 +1,159 @@
+//! Control reads for bad-read comparisons: reads from pores that were alive at the
+//! scans either side of them, sampled to match each selected read on run time and
+//! optionally length.
+
+use std::{borrow::Borrow, collections::{HashMap, HashSet}, io::BufRead, path::Path};
+
+use crate::{open_reader, PoreMuxStats, PoreState, ReadId, ReadMeta, ReadTimestamp};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ControlOptions {
+    /// controls sampled per selected read
+    pub per_read: usize,
+    /// secs a control may start before or after its read
+    pub time_tolerance: f64,
+    /// largest length difference of a control, as a fraction of its read's length
+    pub length_tolerance: Option<f64>,
+    pub seed: u64,
+}
+
+impl Default for ControlOptions {
+    fn default() -> Self {
+        ControlOptions {
+            per_read: 1,
+            time_tolerance: 300.0,
+            length_tolerance: None,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ControlRead {
+    pub read_id: ReadId,
+    /// selected read the control was matched to
+    pub case: ReadId,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ControlSet {
+    /// in the order of the selected reads
+    pub reads: Vec<ControlRead>,
+    /// reads from alive pores that were not selected
+    pub eligible: usize,
+    /// selected reads with fewer than `per_read` controls
+    pub short: usize,
+}
+
+/// splitmix64, so a seed gives the same controls on every platform and version.
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+/// Scan times and states of every pore, in time order.
+fn sorted_scans(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>) -> HashMap<(u32, u8), Vec<(f64, PoreState)>> {
+    pore_mux_map.iter()
+        .map(|(pore, pore_muxs)| {
+            let mut scans = pore_muxs.muxs.iter().map(|muxstat| (muxstat.secs_start, muxstat.pore_state)).collect::<Vec<(f64, PoreState)>>();
+            scans.sort_by(|a, b| a.0.total_cmp(&b.0));
+            (*pore, scans)
+        })
+        .collect()
+}
+
+/// Whether the scans right before and after `secs` on the pore both found it alive.
+/// A read starting exactly with a scan counts as after it.
+fn alive_around(scans: &[(f64, PoreState)], secs: f64) -> bool {
+    let i = scans.partition_point(|(scan_secs, _)| *scan_secs <= secs);
+
+    i > 0 && scans.get(i - 1..=i).is_some_and(|around| around.iter().all(|(_, state)| *state == PoreState::Alive))
+}
+
+/// Samples controls for `selected` from the reads in `read_metas`. Controls are drawn
+/// without replacement, so no read is used twice or is itself a selected read.
+pub fn sample_controls<T: Borrow<ReadTimestamp>>(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, read_metas: &[ReadMeta], selected: &[T], opts: &ControlOptions) -> ControlSet {
+    let selected_ids = selected.iter().map(|ts| &ts.borrow().read_id).collect::<HashSet<&ReadId>>();
+    let lens = read_metas.iter().map(|meta| (&meta.read_id, meta.len_signal)).collect::<HashMap<&ReadId, u64>>();
+
+    let scans = sorted_scans(pore_mux_map);
+
+    // (secs_start, len_signal, read_id) in start time order
+    let mut candidates = read_metas.iter()
+        .filter(|meta| !selected_ids.contains(&meta.read_id))
+        .filter(|meta| scans.get(&(meta.channel, meta.mux)).is_some_and(|scans| alive_around(scans, meta.secs_start())))
+        .map(|meta| (meta.secs_start(), meta.len_signal, &meta.read_id))
+        .collect::<Vec<(f64, u64, &ReadId)>>();
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.2.cmp(b.2)));
+
+    let mut rng = SplitMix64::new(opts.seed);
+    let mut used = vec![false; candidates.len()];
+    let mut ret = ControlSet { eligible: candidates.len(), ..Default::default() };
+
+    for ts in selected.iter().map(Borrow::borrow) {
+        let len = lens.get(&ts.read_id).copied();
+        let from = candidates.partition_point(|(secs, _, _)| *secs < ts.secs_start - opts.time_tolerance);
+        let to = candidates.partition_point(|(secs, _, _)| *secs <= ts.secs_start + opts.time_tolerance);
+
+        let mut pool = (from..to)
+            .filter(|i| !used[*i])
+            .filter(|i| match (opts.length_tolerance, len) {
+                (Some(tolerance), Some(len)) => (candidates[*i].1 as f64 - len as f64).abs() <= tolerance * len as f64,
+                _ => true,
+            })
+            .collect::<Vec<usize>>();
+
+        let n = opts.per_read.min(pool.len());
+        if n < opts.per_read {
+            ret.short += 1;
+        }
+        // partial Fisher-Yates
+        for j in 0..n {
+            let k = j + rng.below(pool.len() - j);
+            pool.swap(j, k);
+            used[pool[j]] = true;
+            ret.reads.push(ControlRead { read_id: candidates[pool[j]].2.clone(), case: ts.read_id.clone() });
+        }
+    }
+
+    ret
+}
+
+/// Read_ids with the given label (`case` or `control`) from a `get --controls` table,
+/// `None` if the file has no `read_id` and `label` columns.
+pub fn load_labelled_read_ids(controls_fpath: &Path, label: &str) -> Option<Vec<String>> {
+    let mut lines = open_reader(controls_fpath).lines();
+    let header = lines.next()?.expect("could not read controls file");
+    let cols = header.split('\t').collect::<Vec<&str>>();
+    let read_id_col = cols.iter().position(|col| *col == "read_id")?;
+    let label_col = cols.iter().position(|col| *col == "label")?;
+
+    let mut ret = Vec::new();
+    for line in lines {
+        let line = line.expect("could not read controls file");
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        if fields.get(label_col) == Some(&label) {
+            if let Some(read_id) = fields.get(read_id_col) {
+                ret.push(read_id.to_string());
+            }
+        }
+    }
+
+    Some(ret)
+}
@@ -0,0 +1,183 @@
+//! Per-name read counts from alignments, and the comparison of selected reads against
+//! all reads, e.g. reads per transcript, with enrichment statistics for every name.
+
+use std::{collections::{HashMap, HashSet}, io::BufRead, path::Path};
+
+use crate::{bh_adjust, fisher_exact, open_reader, read_alignments, ReadId};
+
+#[derive(Clone, Debug)]
+pub struct CountOptions {
+    /// skip secondary and supplementary alignments
+    pub primary_only: bool,
+    pub min_mapq: u8,
+    /// fewest read bases within the alignment
+    pub min_aligned_len: u64,
+    /// reference -> gene, alignments to other references are not counted
+    pub gene_map: Option<HashMap<String, String>>,
+}
+
+impl Default for CountOptions {
+    fn default() -> Self {
+        CountOptions {
+            primary_only: true,
+            min_mapq: 0,
+            min_aligned_len: 0,
+            gene_map: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReadCounts {
+    /// (name, reads), most reads first, then by name
+    pub counts: Vec<(String, u64)>,
+    /// alignment records of the selected reads
+    pub alignments: usize,
+    pub unmapped: usize,
+    /// alignments dropped by the primary, MAPQ or length filters
+    pub filtered: usize,
+    /// alignments to references missing from the gene map
+    pub unlisted: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CountComparison {
+    pub name: String,
+    pub all: u64,
+    pub selected: u64,
+    /// log2 of the name's share of selected reads over its share of all reads, with
+    /// 0.5 added to every name's count, and so 0.5 per name to the totals
+    pub log2_ratio: f64,
+    /// two-sided Fisher's exact test
+    pub p_value: f64,
+    /// Benjamini-Hochberg adjusted `p_value`
+    pub q_value: f64,
+}
+
+/// Reads whitespace separated `count name` lines, or `name count` with a `count_col`
+/// of 1. `uniq -c` and `bad_reads count` output can be read as is. Later lines for a
+/// name replace earlier ones.
+pub fn load_counts(counts_fpath: &Path, count_col: usize) -> Vec<(String, u64)> {
+    let mut ret = Vec::<(String, u64)>::new();
+    let mut idx = HashMap::<String, usize>::new();
+
+    for line in open_reader(counts_fpath).lines() {
+        let line = line.expect("could not read counts file");
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() < 2 {
+            continue;
+        }
+
+        let (count, name) = if count_col == 0 { (fields[0], fields[1]) } else { (fields[1], fields[0]) };
+        let count = count.parse::<u64>().unwrap_or_else(|_| panic!("invalid count {} for {}", count, name));
+        match idx.get(name) {
+            Some(i) => ret[*i].1 = count,
+            None => {
+                idx.insert(name.to_string(), ret.len());
+                ret.push((name.to_string(), count));
+            }
+        }
+    }
+
+    ret
+}
+
+/// Reads `reference gene` lines, e.g. a transcript to gene table.
+pub fn load_gene_map(gene_map_fpath: &Path) -> HashMap<String, String> {
+    let mut ret = HashMap::new();
+
+    for line in open_reader(gene_map_fpath).lines() {
+        let line = line.expect("could not read gene map");
+        let mut fields = line.split_whitespace();
+        if let (Some(reference), Some(gene)) = (fields.next(), fields.next()) {
+            ret.insert(reference.to_string(), gene.to_string());
+        }
+    }
+
+    ret
+}
+
+/// Counts the reads aligned to every reference, or gene with a gene map, only looking
+/// at `read_ids` if given. A read counts once per name however many of its alignments
+/// pass the filters.
+pub fn count_reads(align_fpath: &Path, read_ids: Option<&HashSet<ReadId>>, opts: &CountOptions) -> ReadCounts {
+    let mut ret = ReadCounts::default();
+    let mut seen = HashSet::<(ReadId, String)>::new();
+    let mut counts = HashMap::<String, u64>::new();
+
+    read_alignments(align_fpath, |alignment| {
+        let read_id = ReadId::from(alignment.read_id.as_str());
+        if read_ids.is_some_and(|read_ids| !read_ids.contains(&read_id)) {
+            return;
+        }
+        ret.alignments += 1;
+
+        let Some(target) = &alignment.target else {
+            ret.unmapped += 1;
+            return;
+        };
+        if (opts.primary_only && !alignment.primary) || alignment.mapq < opts.min_mapq || alignment.query_aligned < opts.min_aligned_len {
+            ret.filtered += 1;
+            return;
+        }
+        let name = match &opts.gene_map {
+            Some(gene_map) => match gene_map.get(target) {
+                Some(gene) => gene,
+                None => {
+                    ret.unlisted += 1;
+                    return;
+                }
+            },
+            None => target,
+        };
+
+        if seen.insert((read_id, name.clone())) {
+            *counts.entry(name.clone()).or_insert(0) += 1;
+        }
+    });
+
+    ret.counts = counts.into_iter().collect();
+    ret.counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    ret
+}
+
+/// Merges the two count lists, names missing from one counting zero there, and tests
+/// every name for enrichment among the selected reads. The lists are treated as
+/// independent samples, which is conservative when the selected reads are a subset
+/// of all reads. Names come in `all` order, then those only in `selected`.
+pub fn compare_counts(all: &[(String, u64)], selected: &[(String, u64)]) -> Vec<CountComparison> {
+    let selected_idx = selected.iter().map(|(name, count)| (name.as_str(), *count)).collect::<HashMap<&str, u64>>();
+    let all_idx = all.iter().map(|(name, count)| (name.as_str(), *count)).collect::<HashMap<&str, u64>>();
+
+    let merged = all.iter()
+        .map(|(name, count)| (name, *count, selected_idx.get(name.as_str()).copied().unwrap_or(0)))
+        .chain(selected.iter().filter(|(name, _)| !all_idx.contains_key(name.as_str())).map(|(name, count)| (name, 0, *count)))
+        .collect::<Vec<(&String, u64, u64)>>();
+
+    let all_total = merged.iter().map(|(_, all, _)| all).sum::<u64>();
+    let selected_total = merged.iter().map(|(_, _, selected)| selected).sum::<u64>();
+
+    let p_values = merged.iter()
+        .map(|(_, all, selected)| fisher_exact(*selected, selected_total - selected, *all, all_total - all))
+        .collect::<Vec<f64>>();
+    let q_values = bh_adjust(&p_values);
+    let pseudo_total = 0.5 * merged.len() as f64;
+
+    merged.into_iter()
+        .zip(p_values.into_iter().zip(q_values))
+        .map(|((name, all, selected), (p_value, q_value))| {
+            let selected_share = (selected as f64 + 0.5) / (selected_total as f64 + pseudo_total);
+            let all_share = (all as f64 + 0.5) / (all_total as f64 + pseudo_total);
+
+            CountComparison {
+                name: name.clone(),
+                all,
+                selected,
+                log2_ratio: (selected_share / all_share).log2(),
+                p_value,
+                q_value,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,224 @@
+//! External sorting of read timestamps for runs too large to sort in memory. Reads
+//! are buffered up to a memory budget, each full buffer is sorted and spilled to a
+//! run file in the temp directory (`TMPDIR`), and the runs are k-way merged back
+//! into one sorted stream.
+
+use std::{cmp::Ordering, collections::BinaryHeap, env, fs::{self, File, OpenOptions}, io::{self, BufReader, BufWriter, Read, Write}, mem, path::PathBuf, process, sync::atomic::{AtomicUsize, Ordering as AtomicOrdering}, vec};
+
+use crate::{cmp_read_timestamps, ReadId, ReadTimestamp};
+
+const TAG_UUID: u8 = 0;
+const TAG_OTHER: u8 = 1;
+
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Collects read timestamps, spilling sorted runs to disk whenever the buffered
+/// reads would exceed the memory budget.
+pub struct TimestampSorter {
+    memory_budget: Option<usize>,
+    buf: Vec<ReadTimestamp>,
+    buf_bytes: usize,
+    runs: Vec<Run>,
+}
+
+/// Read timestamps in `sort_read_timestamps` order.
+pub struct SortedTimestamps {
+    source: Source,
+}
+
+enum Source {
+    Memory(vec::IntoIter<ReadTimestamp>),
+    Merge {
+        runs: Vec<Run>,
+        heads: BinaryHeap<Head>,
+    },
+}
+
+/// A spilled run, removed again once it is dropped.
+struct Run {
+    fpath: PathBuf,
+    reader: BufReader<File>,
+}
+
+struct Head {
+    ts: ReadTimestamp,
+    run: usize,
+}
+
+fn read_id_bytes(read_id: &ReadId) -> usize {
+    match read_id {
+        ReadId::Uuid(_) => 0,
+        ReadId::Other(read_id) => read_id.len(),
+    }
+}
+
+impl TimestampSorter {
+    /// `memory_budget` is in bytes, `None` sorts everything in memory.
+    pub fn new(memory_budget: Option<usize>) -> Self {
+        TimestampSorter {
+            memory_budget,
+            buf: Vec::new(),
+            buf_bytes: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, ts: ReadTimestamp) {
+        self.buf_bytes += mem::size_of::<ReadTimestamp>() + read_id_bytes(&ts.read_id);
+        self.buf.push(ts);
+
+        if self.memory_budget.is_some_and(|memory_budget| self.buf_bytes >= memory_budget) {
+            self.spill();
+        }
+    }
+
+    /// Number of runs spilled to disk so far.
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn spill(&mut self) {
+        self.buf.sort_by(cmp_read_timestamps);
+        let run = Run::write(&self.buf).expect("could not write sorted run to the temp directory");
+        self.runs.push(run);
+
+        // release the buffer rather than keeping its peak capacity around
+        self.buf = Vec::new();
+        self.buf_bytes = 0;
+    }
+
+    pub fn finish(mut self) -> SortedTimestamps {
+        if self.runs.is_empty() {
+            self.buf.sort_by(cmp_read_timestamps);
+            return SortedTimestamps { source: Source::Memory(self.buf.into_iter()) };
+        }
+        if !self.buf.is_empty() {
+            self.spill();
+        }
+
+        let mut runs = mem::take(&mut self.runs);
+        let mut heads = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(ts) = run.next() {
+                heads.push(Head { ts, run: i });
+            }
+        }
+
+        SortedTimestamps { source: Source::Merge { runs, heads } }
+    }
+}
+
+impl Iterator for SortedTimestamps {
+    type Item = ReadTimestamp;
+
+    fn next(&mut self) -> Option<ReadTimestamp> {
+        match &mut self.source {
+            Source::Memory(reads) => reads.next(),
+            Source::Merge { runs, heads } => {
+                let Head { ts, run } = heads.pop()?;
+                if let Some(next) = runs[run].next() {
+                    heads.push(Head { ts: next, run });
+                }
+                Some(ts)
+            }
+        }
+    }
+}
+
+impl Run {
+    fn write(read_timestamps: &[ReadTimestamp]) -> io::Result<Run> {
+        let fpath = env::temp_dir().join(format!(
+            "bad_reads_{}_{}.run",
+            process::id(),
+            RUN_COUNTER.fetch_add(1, AtomicOrdering::Relaxed),
+        ));
+        let mut writer = BufWriter::new(OpenOptions::new().create_new(true).write(true).open(&fpath)?);
+
+        for ts in read_timestamps.iter() {
+            match &ts.read_id {
+                ReadId::Uuid(uuid) => {
+                    writer.write_all(&[TAG_UUID])?;
+                    writer.write_all(uuid)?;
+                }
+                ReadId::Other(read_id) => {
+                    writer.write_all(&[TAG_OTHER])?;
+                    writer.write_all(&(read_id.len() as u16).to_le_bytes())?;
+                    writer.write_all(read_id.as_bytes())?;
+                }
+            }
+            writer.write_all(&ts.secs_start.to_le_bytes())?;
+            writer.write_all(&ts.channel.to_le_bytes())?;
+            writer.write_all(&[ts.pore])?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        Ok(Run { reader: BufReader::new(File::open(&fpath)?), fpath })
+    }
+
+    fn read_entry(&mut self) -> io::Result<ReadTimestamp> {
+        let mut tag = [0u8; 1];
+        self.reader.read_exact(&mut tag)?;
+
+        let read_id = if tag[0] == TAG_UUID {
+            let mut uuid = [0u8; 16];
+            self.reader.read_exact(&mut uuid)?;
+            ReadId::Uuid(uuid)
+        } else {
+            let mut len = [0u8; 2];
+            self.reader.read_exact(&mut len)?;
+            let mut read_id = vec![0u8; u16::from_le_bytes(len) as usize];
+            self.reader.read_exact(&mut read_id)?;
+            String::from_utf8(read_id).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?.into()
+        };
+
+        let mut secs_start = [0u8; 8];
+        let mut channel = [0u8; 4];
+        let mut pore = [0u8; 1];
+        self.reader.read_exact(&mut secs_start)?;
+        self.reader.read_exact(&mut channel)?;
+        self.reader.read_exact(&mut pore)?;
+
+        Ok(ReadTimestamp {
+            read_id,
+            secs_start: f64::from_le_bytes(secs_start),
+            channel: u32::from_le_bytes(channel),
+            pore: pore[0],
+        })
+    }
+
+    fn next(&mut self) -> Option<ReadTimestamp> {
+        match self.read_entry() {
+            Ok(ts) => Some(ts),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => panic!("could not read sorted run {}: {}", self.fpath.display(), err),
+        }
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.fpath);
+    }
+}
+
+// BinaryHeap is a max-heap, so heads compare in reverse to pop the earliest read first
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_read_timestamps(&other.ts, &self.ts).then(other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
@@ -0,0 +1,159 @@
+//! Raw signal features of single reads, to check whether selected reads differ from
+//! the rest at the signal level.
+
+use std::{fmt::Write, path::Path};
+
+use slow5::{FileReader, Record, RecordExt};
+
+use crate::{log_warn, ValueRange};
+
+#[derive(Clone, Copy, Debug)]
+pub struct FeatureOptions {
+    /// length of the start and end windows, in secs
+    pub edge_secs: f64,
+    /// pA range outside which a sample counts as saturated. By default samples at
+    /// the limits of the ADC, raw 0 or `digitisation - 1`, are saturated.
+    pub saturation: Option<ValueRange>,
+}
+
+impl Default for FeatureOptions {
+    fn default() -> Self {
+        FeatureOptions {
+            edge_secs: 1.0,
+            saturation: None,
+        }
+    }
+}
+
+/// Summary of a read's signal in pA. Reads without samples have NaN statistics.
+#[derive(Clone, Debug)]
+pub struct SignalFeatures {
+    pub read_id: String,
+    pub samples: usize,
+    /// secs
+    pub duration: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// population standard deviation
+    pub sd: f64,
+    /// unscaled median absolute deviation
+    pub mad: f64,
+    pub min: f64,
+    pub max: f64,
+    pub saturated_frac: f64,
+    /// mean of the first `edge_secs`
+    pub start_mean: f64,
+    /// mean of the last `edge_secs`
+    pub end_mean: f64,
+}
+
+pub const FEATURES_TSV_HEADER: &str = "read_id\tsamples\tduration\tmean\tmedian\tsd\tmad\tmin\tmax\tsaturated_frac\tstart_mean\tend_mean";
+
+fn mean(vals: &[f64]) -> f64 {
+    vals.iter().sum::<f64>() / vals.len() as f64
+}
+
+/// Median of `vals`, which get reordered.
+fn median(vals: &mut [f64]) -> f64 {
+    if vals.is_empty() { return f64::NAN; }
+
+    let mid = vals.len() / 2;
+    let even = vals.len().is_multiple_of(2);
+    let (lower, upper, _) = vals.select_nth_unstable_by(mid, f64::total_cmp);
+    if even {
+        let below = lower.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (below + *upper) / 2.0
+    } else {
+        *upper
+    }
+}
+
+/// Computes the features of a raw (ADC) signal, converted to pA as
+/// `(raw + offset) * range / digitisation`.
+pub fn signal_features(read_id: &str, raw_signal: &[i16], digitisation: f64, offset: f64, range: f64, sampling_rate: f64, opts: &FeatureOptions) -> SignalFeatures {
+    let to_pa = |raw: i16| (raw as f64 + offset) * range / digitisation;
+    let pa = raw_signal.iter().map(|raw| to_pa(*raw)).collect::<Vec<f64>>();
+
+    let saturated = match opts.saturation {
+        Some(limits) => pa.iter().filter(|val| !limits.contains(**val)).count(),
+        None => raw_signal.iter().filter(|raw| **raw <= 0 || **raw as f64 >= digitisation - 1.0).count(),
+    };
+
+    let mean = mean(&pa);
+    let sd = (pa.iter().map(|val| (val - mean).powi(2)).sum::<f64>() / pa.len() as f64).sqrt();
+    let edge = ((opts.edge_secs * sampling_rate).round() as usize).max(1).min(pa.len());
+    let start_mean = self::mean(&pa[..edge]);
+    let end_mean = self::mean(&pa[pa.len() - edge..]);
+    let min = pa.iter().copied().reduce(f64::min).unwrap_or(f64::NAN);
+    let max = pa.iter().copied().reduce(f64::max).unwrap_or(f64::NAN);
+
+    let mut scratch = pa;
+    let median = median(&mut scratch);
+    for val in scratch.iter_mut() {
+        *val = (*val - median).abs();
+    }
+    let mad = self::median(&mut scratch);
+
+    SignalFeatures {
+        read_id: read_id.into(),
+        samples: raw_signal.len(),
+        duration: raw_signal.len() as f64 / sampling_rate,
+        mean,
+        median,
+        sd,
+        mad,
+        min,
+        max,
+        saturated_frac: saturated as f64 / raw_signal.len() as f64,
+        start_mean,
+        end_mean,
+    }
+}
+
+impl SignalFeatures {
+    pub fn from_record(rec: &Record, opts: &FeatureOptions) -> SignalFeatures {
+        let read_id = String::from_utf8_lossy(rec.read_id());
+        let raw_signal = rec.raw_signal_iter().collect::<Vec<i16>>();
+
+        signal_features(&read_id, &raw_signal, rec.digitisation(), rec.offset(), rec.range(), rec.sampling_rate(), opts)
+    }
+
+    /// One row below `FEATURES_TSV_HEADER`.
+    pub fn tsv_row(&self) -> String {
+        let mut ret = format!("{}\t{}\t{:.4}", self.read_id, self.samples, self.duration);
+        for val in [self.mean, self.median, self.sd, self.mad, self.min, self.max] {
+            write!(ret, "\t{:.3}", val).unwrap();
+        }
+        write!(ret, "\t{:.6}\t{:.3}\t{:.3}", self.saturated_frac, self.start_mean, self.end_mean).unwrap();
+
+        ret
+    }
+}
+
+/// Calls `f` with the features of every listed read, or of every read in the file
+/// without a list. Returns the listed read_ids that are not in the slow5.
+pub fn extract_features<F: FnMut(SignalFeatures)>(slow5_fpath: &Path, read_ids: Option<&[String]>, opts: &FeatureOptions, mut f: F) -> Vec<String> {
+    let mut slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
+    let mut missing = Vec::new();
+
+    match read_ids {
+        Some(read_ids) => {
+            for read_id in read_ids.iter() {
+                match slow5.get_record(read_id.as_str()) {
+                    Ok(rec) => f(SignalFeatures::from_record(&rec, opts)),
+                    Err(_) => missing.push(read_id.clone()),
+                }
+            }
+        }
+        None => {
+            for rec in slow5.records() {
+                match rec {
+                    Ok(rec) => f(SignalFeatures::from_record(&rec, opts)),
+                    Err(err) => log_warn!("error reading record {:?}, skipping...", err),
+                }
+            }
+        }
+    }
+
+    missing
+}
@@ -0,0 +1,170 @@
+//! Physical positions of channels on a flowcell, so reads and pores can be grouped by
+//! region, block or neighbourhood rather than by channel number.
+
+use std::path::Path;
+
+use slow5::FileReader;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlowcellLayout {
+    /// 512 channels on 16 rows of 32
+    MinIon,
+    /// 126 channels on 9 rows of 14. There is no published Flongle channel map, so
+    /// channels are laid out row by row and neighbours are approximate.
+    Flongle,
+    /// 3000 channels on 25 rows of 120, in 12 blocks of 250
+    PromethIon,
+}
+
+/// Zero-based position on the flowcell, row 0 at the top.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChannelPos {
+    pub row: u32,
+    pub col: u32,
+}
+
+const MINION_LOWER: [u32; 8] = [33, 481, 417, 353, 289, 225, 161, 97];
+const MINION_UPPER: [u32; 8] = [8, 456, 392, 328, 264, 200, 136, 72];
+const FLONGLE_COLS: u32 = 14;
+const PROMETHION_BLOCK: u32 = 250;
+const PROMETHION_BLOCK_COLS: u32 = 10;
+
+impl FlowcellLayout {
+    pub fn from_name(name: &str) -> Option<FlowcellLayout> {
+        match name {
+            "minion" => Some(FlowcellLayout::MinIon),
+            "flongle" => Some(FlowcellLayout::Flongle),
+            "promethion" => Some(FlowcellLayout::PromethIon),
+            _ => None,
+        }
+    }
+
+    /// Picks the layout from the `device_type` and `flow_cell_product_code` header
+    /// attributes. Flongle adapters fit MinION and GridION devices, so the product
+    /// code is checked first.
+    pub fn detect(device_type: Option<&str>, product_code: Option<&str>) -> Option<FlowcellLayout> {
+        let product_code = product_code.map(|code| code.to_ascii_uppercase());
+        match product_code.as_deref() {
+            Some(code) if code.starts_with("FLO-FLG") => return Some(FlowcellLayout::Flongle),
+            Some(code) if code.starts_with("FLO-PRO") => return Some(FlowcellLayout::PromethIon),
+            Some(code) if code.starts_with("FLO-MIN") => return Some(FlowcellLayout::MinIon),
+            _ => {}
+        }
+
+        match device_type.map(|device_type| device_type.to_ascii_lowercase()).as_deref() {
+            Some("minion" | "gridion" | "mk1c") => Some(FlowcellLayout::MinIon),
+            Some("promethion" | "p2_solo" | "p2") => Some(FlowcellLayout::PromethIon),
+            _ => None,
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        match self {
+            FlowcellLayout::MinIon => 512,
+            FlowcellLayout::Flongle => 126,
+            FlowcellLayout::PromethIon => 3000,
+        }
+    }
+
+    /// (rows, cols)
+    pub fn dims(&self) -> (u32, u32) {
+        match self {
+            FlowcellLayout::MinIon => (16, 32),
+            FlowcellLayout::Flongle => (9, FLONGLE_COLS),
+            FlowcellLayout::PromethIon => (25, 120),
+        }
+    }
+
+    /// Position of a 1-based channel, `None` if the layout has no such channel.
+    pub fn position(&self, channel: u32) -> Option<ChannelPos> {
+        if channel == 0 || channel > self.channels() {
+            return None;
+        }
+        let idx = channel - 1;
+
+        Some(match self {
+            // columns come in groups of 4 with a run of 8 channels counting up on the
+            // top half and a run counting down on the bottom half
+            FlowcellLayout::MinIon => {
+                let group = MINION_LOWER.iter().position(|lower| (*lower..*lower + 32).contains(&channel));
+                match group {
+                    Some(group) => {
+                        let offset = channel - MINION_LOWER[group];
+                        ChannelPos { row: offset % 8, col: group as u32 * 4 + offset / 8 }
+                    }
+                    None => {
+                        let group = MINION_UPPER.iter().position(|upper| (upper - 7..upper + 25).contains(&channel))?;
+                        let offset = channel + 7 - MINION_UPPER[group];
+                        ChannelPos { row: 15 - offset % 8, col: group as u32 * 4 + offset / 8 }
+                    }
+                }
+            }
+            FlowcellLayout::Flongle => ChannelPos { row: idx / FLONGLE_COLS, col: idx % FLONGLE_COLS },
+            FlowcellLayout::PromethIon => {
+                let (block, offset) = (idx / PROMETHION_BLOCK, idx % PROMETHION_BLOCK);
+                ChannelPos {
+                    row: offset / PROMETHION_BLOCK_COLS,
+                    col: block * PROMETHION_BLOCK_COLS + offset % PROMETHION_BLOCK_COLS,
+                }
+            }
+        })
+    }
+
+    /// Channel at a position, `None` off the flowcell.
+    pub fn channel_at(&self, pos: ChannelPos) -> Option<u32> {
+        let (rows, cols) = self.dims();
+        if pos.row >= rows || pos.col >= cols {
+            return None;
+        }
+
+        match self {
+            FlowcellLayout::MinIon => {
+                let (group, offset) = (pos.col / 4, pos.col % 4 * 8);
+                if pos.row < 8 {
+                    Some(MINION_LOWER[group as usize] + offset + pos.row)
+                } else {
+                    Some(MINION_UPPER[group as usize] + offset - (pos.row - 8))
+                }
+            }
+            FlowcellLayout::Flongle => Some(pos.row * FLONGLE_COLS + pos.col + 1),
+            FlowcellLayout::PromethIon => {
+                let (block, col) = (pos.col / PROMETHION_BLOCK_COLS, pos.col % PROMETHION_BLOCK_COLS);
+                Some(block * PROMETHION_BLOCK + pos.row * PROMETHION_BLOCK_COLS + col + 1)
+            }
+        }
+    }
+
+    /// Channels sharing an edge with `channel`.
+    pub fn neighbours(&self, channel: u32) -> Vec<u32> {
+        let Some(pos) = self.position(channel) else { return Vec::new(); };
+
+        [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+            .filter_map(|(d_row, d_col)| {
+                let row = pos.row.checked_add_signed(*d_row)?;
+                let col = pos.col.checked_add_signed(*d_col)?;
+                self.channel_at(ChannelPos { row, col })
+            })
+            .collect()
+    }
+
+    /// Index of the `block_rows` x `block_cols` block holding `channel`, counted row
+    /// by row from the top left.
+    pub fn block(&self, channel: u32, block_rows: u32, block_cols: u32) -> Option<u32> {
+        let pos = self.position(channel)?;
+        let blocks_per_row = self.dims().1.div_ceil(block_cols);
+
+        Some(pos.row / block_rows * blocks_per_row + pos.col / block_cols)
+    }
+}
+
+/// Layout named by the slow5 header, `None` if the attributes are missing or unknown.
+pub fn flowcell_layout(slow5: &FileReader) -> Option<FlowcellLayout> {
+    let header = slow5.header();
+    let attr = |name: &str| header.get_attribute(name, 0).ok().and_then(|val| std::str::from_utf8(val).ok());
+
+    FlowcellLayout::detect(attr("device_type"), attr("flow_cell_product_code"))
+}
+
+pub fn read_flowcell_layout(slow5_fpath: &Path) -> Option<FlowcellLayout> {
+    flowcell_layout(&FileReader::open(slow5_fpath).expect("could not open slow5"))
+}
@@ -1,76 +1,488 @@
-use std::{collections::HashMap, fs::read_to_string, path::Path};
+use std::{borrow::Borrow, cmp::Ordering, collections::{HashMap, HashSet}, fs::{read_to_string, File}, io::{BufRead, BufReader}, path::Path, thread};
 
-use slow5::{FileReader, RecordExt};
+use flate2::bufread::MultiGzDecoder;
+use slow5::{EnumField, FileReader, Record, RecordExt};
+
+mod align;
+mod align_qc;
+mod basecall_qc;
+mod blow5;
+mod cache;
+mod control;
+mod counts;
+mod extsort;
+mod features;
+mod layout;
+mod lifetime;
+mod logging;
+mod plot;
+mod query;
+mod read_ids;
+mod spatial;
+mod stats;
+
+pub use align::*;
+pub use align_qc::*;
+pub use basecall_qc::*;
+pub use cache::{cache_path, read_meta_cache, write_meta_cache};
+pub use control::*;
+pub use counts::*;
+pub use extsort::*;
+pub use features::*;
+pub use layout::*;
+pub use lifetime::*;
+pub use logging::*;
+pub use plot::*;
+pub use query::*;
+pub use read_ids::*;
+pub use spatial::*;
+pub use stats::*;
 
 #[derive(Default, Clone)]
-pub struct PoreMuxStats<'a> {
-    pub muxs: Vec<MuxStat<'a>>,
-    pub last_entry: usize,
+pub struct PoreMuxStats {
+    pub muxs: Vec<MuxStat>,
 }
 
 #[derive(Default, Clone, Copy)]
-pub struct MuxStat<'a> {
+pub struct MuxStat {
     pub secs_start: f64,
-    pub read_secs_start: f64,
-    pub read_id: Option<&'a String>,
     pub pore_state: PoreState
 }
 
+#[derive(Clone, Debug)]
 pub struct ReadTimestamp {
-    pub read_id: String,
+    pub read_id: ReadId,
     pub secs_start: f64,
     pub channel: u32,
     pub pore: u8,
 }
 
-#[derive(Default, PartialEq, Eq, Clone, Copy)]
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum PoreState {
     #[default]
     Dead,
     Alive,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ReadMode {
     First,
     Last,
 }
 
+#[derive(Clone, Copy)]
 pub enum FilterMode {
     Odd,
     Even,
 }
 
-pub fn gen_read_timestamps(slow5_fpath: &Path) -> Vec<ReadTimestamp> {
-    let mut ret = Vec::new();
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum FilterLogic {
+    #[default]
+    And,
+    Or,
+}
+
+/// Half-open `[min, max)` range, either side may be left open.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct ValueRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+pub enum ReadCond {
+    /// odd or even channels, always ANDed with the other conditions
+    Channel(FilterMode),
+    Mux(Vec<u8>),
+    StartTime(ValueRange),
+    SignalLen(ValueRange),
+    Duration(ValueRange),
+    EndReason(Vec<String>),
+    /// channel on the given rows and cols of the flowcell
+    Region(FlowcellLayout, ValueRange, ValueRange),
+}
+
+#[derive(Default)]
+pub struct ReadFilter {
+    pub conds: Vec<ReadCond>,
+    pub logic: FilterLogic,
+}
+
+#[derive(Default)]
+pub struct FilteredReads {
+    pub passed: Vec<String>,
+    pub failed: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+pub struct ReadInfo {
+    pub channel: u32,
+    pub mux: u8,
+    pub secs_start: f64,
+    pub len_signal: u64,
+    pub sampling_rate: f64,
+    pub end_reason: Option<String>,
+}
+
+impl ValueRange {
+    /// Parses `min:max`, `min:` or `:max`.
+    pub fn parse(s: &str) -> Option<ValueRange> {
+        let (min, max) = s.split_once(':')?;
+        let bound = |b: &str| if b.is_empty() { Ok(None) } else { b.parse::<f64>().map(Some) };
+        
+        Some(ValueRange {
+            min: bound(min).ok()?,
+            max: bound(max).ok()?,
+        })
+    }
     
-    let mut slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
-    for rec in slow5.records() {
-        if rec.is_err() {
-            println!("error reading record {:?}, skipping...", rec.err());
-            continue;
+    pub fn contains(&self, val: f64) -> bool {
+        self.min.is_none_or(|min| val >= min) && self.max.is_none_or(|max| val < max)
+    }
+}
+
+impl ReadCond {
+    pub fn matches(&self, info: &ReadInfo) -> bool {
+        match self {
+            ReadCond::Channel(FilterMode::Odd) => !info.channel.is_multiple_of(2),
+            ReadCond::Channel(FilterMode::Even) => info.channel.is_multiple_of(2),
+            ReadCond::Mux(muxs) => muxs.contains(&info.mux),
+            ReadCond::StartTime(range) => range.contains(info.secs_start),
+            ReadCond::SignalLen(range) => range.contains(info.len_signal as f64),
+            ReadCond::Duration(range) => range.contains(info.duration()),
+            ReadCond::EndReason(reasons) => match &info.end_reason {
+                Some(end_reason) => reasons.contains(end_reason),
+                None => false,
+            },
+            ReadCond::Region(layout, rows, cols) => layout.position(info.channel)
+                .is_some_and(|pos| rows.contains(pos.row as f64) && cols.contains(pos.col as f64)),
         }
-        let rec = rec.unwrap();
-        
+    }
+}
+
+impl ReadFilter {
+    pub fn matches(&self, info: &ReadInfo) -> bool {
+        match self.logic {
+            FilterLogic::And => self.conds.iter().all(|cond| cond.matches(info)),
+            FilterLogic::Or => {
+                // the channel mode is a pre-filter, the logic only combines the rest
+                let is_channel = |cond: &&ReadCond| matches!(cond, ReadCond::Channel(_));
+                let mut rest = self.conds.iter().filter(|cond| !is_channel(cond)).peekable();
+                
+                self.conds.iter().filter(is_channel).all(|cond| cond.matches(info))
+                    && (rest.peek().is_none() || rest.any(|cond| cond.matches(info)))
+            }
+        }
+    }
+}
+
+impl From<FilterMode> for ReadFilter {
+    fn from(filter_mode: FilterMode) -> Self {
+        ReadFilter {
+            conds: vec![ReadCond::Channel(filter_mode)],
+            ..Default::default()
+        }
+    }
+}
+
+impl ReadInfo {
+    pub fn from_record(rec: &Record, end_reason_labels: &[String]) -> ReadInfo {
         let channel = rec.get_aux_field::<&str>("channel_number").expect("could not load aux_field `channel_number`");
         let channel = channel.parse::<u32>().expect("could not parse channel_number as u32");
-        let pore = rec.get_aux_field::<u8>("start_mux").expect("could not load aux_field `start_mux`");
+        let mux = rec.get_aux_field::<u8>("start_mux").expect("could not load aux_field `start_mux`");
+        let samples_start = rec.get_aux_field::<u64>("start_time").expect("could not load aux_field `start_time`");
         
+        let end_reason = match rec.get_aux_field::<EnumField>("end_reason") {
+            Ok(EnumField(idx)) => end_reason_labels.get(idx).cloned(),
+            Err(_) => None,
+        };
+        
+        ReadInfo {
+            channel,
+            mux,
+            secs_start: samples_start as f64 / rec.sampling_rate(),
+            len_signal: rec.len_signal(),
+            sampling_rate: rec.sampling_rate(),
+            end_reason,
+        }
+    }
+    
+    pub fn duration(&self) -> f64 {
+        self.len_signal as f64 / self.sampling_rate
+    }
+}
+
+/// Opens a text or binary input, transparently decompressing gzip/bgzf.
+pub(crate) fn open_reader(fpath: &Path) -> Box<dyn BufRead> {
+    let mut reader = BufReader::new(File::open(fpath).expect("could not open input file"));
+    
+    let is_gzip = reader.fill_buf().expect("could not read input file").starts_with(&[0x1f, 0x8b]);
+    if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    }
+}
+
+fn end_reason_labels(slow5: &FileReader) -> Vec<String> {
+    match slow5.iter_aux_enum_labels("end_reason") {
+        Ok(labels) => labels.map(|label| String::from_utf8_lossy(label).into_owned()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Per-read fields read from the slow5 file that timestamps are built from, as
+/// stored in the timestamp cache.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReadMeta {
+    pub read_id: ReadId,
+    pub channel: u32,
+    pub mux: u8,
+    pub samples_start: u64,
+    pub sampling_rate: f64,
+    pub len_signal: u64,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// always scan the slow5 file
+    #[default]
+    Off,
+    /// reuse a valid cache, otherwise scan and write one
+    ReadWrite,
+    /// scan and overwrite the cache
+    Rebuild,
+}
+
+#[derive(Clone, Copy)]
+pub struct ScanOptions {
+    /// number of threads decoding slow5 records
+    pub threads: usize,
+    /// parse blow5 records without decoding their raw signal where the file allows it
+    pub meta_only: bool,
+    pub cache: CacheMode,
+    /// bytes of read timestamps `stream_read_timestamps` keeps in memory before
+    /// spilling sorted runs to disk, `None` for no limit
+    pub memory_budget: Option<usize>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            threads: 1,
+            meta_only: true,
+            cache: CacheMode::Off,
+            memory_budget: None,
+        }
+    }
+}
+
+impl ReadMeta {
+    pub fn from_record(rec: &Record) -> ReadMeta {
+        let channel = rec.get_aux_field::<&str>("channel_number").expect("could not load aux_field `channel_number`");
+        let channel = channel.parse::<u32>().expect("could not parse channel_number as u32");
+        let mux = rec.get_aux_field::<u8>("start_mux").expect("could not load aux_field `start_mux`");
         let samples_start = rec.get_aux_field::<u64>("start_time").expect("could not load aux_field `start_time`");
-        let secs_start = samples_start as f64 / rec.sampling_rate();
 
-        ret.push(ReadTimestamp {
-            read_id: String::from_utf8(rec.read_id().to_vec()).expect("could not get read_id from rec"),
-            secs_start,
+        ReadMeta {
+            read_id: std::str::from_utf8(rec.read_id()).expect("could not get read_id from rec").into(),
             channel,
-            pore
-        })
+            mux,
+            samples_start,
+            sampling_rate: rec.sampling_rate(),
+            len_signal: rec.len_signal(),
+        }
     }
     
-    ret.sort_by(|a, b| a.secs_start.partial_cmp(&b.secs_start).unwrap());
+    pub fn secs_start(&self) -> f64 {
+        self.samples_start as f64 / self.sampling_rate
+    }
+}
+
+impl From<ReadMeta> for ReadTimestamp {
+    fn from(meta: ReadMeta) -> Self {
+        ReadTimestamp {
+            secs_start: meta.secs_start(),
+            read_id: meta.read_id,
+            channel: meta.channel,
+            pore: meta.mux,
+        }
+    }
+}
+
+pub fn gen_read_timestamps(slow5_fpath: &Path) -> Vec<ReadTimestamp> {
+    gen_read_timestamps_with(slow5_fpath, &ScanOptions::default())
+}
+
+pub fn gen_read_timestamps_with(slow5_fpath: &Path, opts: &ScanOptions) -> Vec<ReadTimestamp> {
+    let mut ret = load_read_meta(slow5_fpath, opts)
+        .into_iter()
+        .map(ReadTimestamp::from)
+        .collect::<Vec<ReadTimestamp>>();
+    
+    sort_read_timestamps(&mut ret);
 
     ret
 }
 
+/// Sorts by start time, ties are broken on channel, pore then read_id so the order
+/// does not depend on how the slow5 was scanned.
+pub fn sort_read_timestamps(read_timestamps: &mut [ReadTimestamp]) {
+    read_timestamps.sort_by(cmp_read_timestamps);
+}
+
+pub(crate) fn cmp_read_timestamps(a: &ReadTimestamp, b: &ReadTimestamp) -> Ordering {
+    a.secs_start.total_cmp(&b.secs_start)
+        .then(a.channel.cmp(&b.channel))
+        .then(a.pore.cmp(&b.pore))
+        .then_with(|| a.read_id.cmp(&b.read_id))
+}
+
+/// Sorted read timestamps, like `gen_read_timestamps_with`, but with at most
+/// `opts.memory_budget` of them held in memory at a time. The slow5 file is scanned
+/// on a single thread.
+pub fn stream_read_timestamps(slow5_fpath: &Path, opts: &ScanOptions) -> SortedTimestamps {
+    let mut sorter = TimestampSorter::new(opts.memory_budget);
+    for_each_read_meta(slow5_fpath, opts, |meta| sorter.push(meta.into()));
+    
+    sorter.finish()
+}
+
+/// Per-read metadata in slow5 file order, from the cache next to the slow5 file when
+/// `opts.cache` allows it.
+pub fn load_read_meta(slow5_fpath: &Path, opts: &ScanOptions) -> Vec<ReadMeta> {
+    let cache_fpath = cache::cache_path(slow5_fpath);
+    
+    if opts.cache == CacheMode::ReadWrite {
+        if let Some(ret) = cache::read_meta_cache(&cache_fpath, slow5_fpath) {
+            return ret;
+        }
+    }
+    
+    let ret = scan_read_meta(slow5_fpath, opts);
+    
+    if opts.cache != CacheMode::Off {
+        if let Err(err) = cache::write_meta_cache(&cache_fpath, slow5_fpath, &ret) {
+            log_warn!("could not write timestamp cache {}: {}", cache_fpath.display(), err);
+        }
+    }
+    
+    ret
+}
+
+/// Streaming counterpart of `load_read_meta`, calls `f` for every read in slow5 file
+/// order without collecting them.
+pub fn for_each_read_meta<F: FnMut(ReadMeta)>(slow5_fpath: &Path, opts: &ScanOptions, mut f: F) {
+    let cache_fpath = cache::cache_path(slow5_fpath);
+    
+    if opts.cache == CacheMode::ReadWrite && cache::for_each_cached_meta(&cache_fpath, slow5_fpath, &mut f) {
+        log_debug!("read timestamps from cache {}", cache_fpath.display());
+        return;
+    }
+    
+    let mut cache_writer = match opts.cache {
+        CacheMode::Off => None,
+        _ => cache::CacheWriter::create(&cache_fpath, slow5_fpath)
+            .inspect_err(|err| log_warn!("could not write timestamp cache {}: {}", cache_fpath.display(), err))
+            .ok(),
+    };
+    let progress = Progress::new("scanning slow5");
+    let mut push = |meta: ReadMeta| {
+        if let Some(writer) = cache_writer.as_mut() {
+            if let Err(err) = writer.push(&meta) {
+                log_warn!("could not write timestamp cache {}: {}", cache_fpath.display(), err);
+                cache_writer = None;
+            }
+        }
+        progress.inc();
+        f(meta);
+    };
+    
+    if !(opts.meta_only && blow5::for_each_read_meta(slow5_fpath, &mut push)) {
+        let mut slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
+        for rec in slow5.records() {
+            match rec {
+                Ok(rec) => push(ReadMeta::from_record(&rec)),
+                Err(err) => log_warn!("error reading record {:?}, skipping...", err),
+            }
+        }
+    }
+    progress.finish();
+    
+    if let Some(Err(err)) = cache_writer.map(cache::CacheWriter::finish) {
+        log_warn!("could not write timestamp cache {}: {}", cache_fpath.display(), err);
+    }
+}
+
+/// Scans the slow5 file, reporting progress to stderr.
+pub fn scan_read_meta(slow5_fpath: &Path, opts: &ScanOptions) -> Vec<ReadMeta> {
+    let progress = Progress::new("scanning slow5");
+    let ret = scan_read_meta_with(slow5_fpath, opts, &progress);
+    progress.finish();
+    
+    ret
+}
+
+fn scan_read_meta_with(slow5_fpath: &Path, opts: &ScanOptions, progress: &Progress) -> Vec<ReadMeta> {
+    if opts.meta_only {
+        if let Some(ret) = blow5::scan_read_meta(slow5_fpath, opts.threads, progress) {
+            return ret;
+        }
+        log_debug!("{} can't be read without decoding, falling back to slow5lib", slow5_fpath.display());
+    }
+    
+    let mut slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
+    
+    if opts.threads > 1 {
+        return scan_read_meta_par(&slow5, slow5_fpath, opts.threads, progress);
+    }
+    
+    let mut ret = Vec::new();
+    for rec in slow5.records() {
+        if rec.is_err() {
+            log_warn!("error reading record {:?}, skipping...", rec.err());
+            continue;
+        }
+        ret.push(ReadMeta::from_record(&rec.unwrap()));
+        progress.inc();
+    }
+    
+    ret
+}
+
+/// Splits the indexed read_ids into one contiguous chunk per thread. slow5 readers
+/// can't be shared across threads, so each worker opens its own.
+fn scan_read_meta_par(slow5: &FileReader, slow5_fpath: &Path, threads: usize, progress: &Progress) -> Vec<ReadMeta> {
+    let read_ids = slow5.iter_read_ids()
+        .expect("could not list read_ids from the slow5 index")
+        .map(|read_id| read_id.to_vec())
+        .collect::<Vec<Vec<u8>>>();
+    let chunk_size = read_ids.len().div_ceil(threads).max(1);
+    
+    thread::scope(|scope| {
+        let workers = read_ids.chunks(chunk_size)
+            .map(|read_ids| scope.spawn(move || {
+                let slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
+                let mut ret = Vec::with_capacity(read_ids.len());
+                
+                for read_id in read_ids.iter() {
+                    match slow5.get_record(read_id.clone()) {
+                        Ok(rec) => ret.push(ReadMeta::from_record(&rec)),
+                        Err(err) => log_warn!("error reading record {:?}, skipping...", err),
+                    }
+                    progress.inc();
+                }
+                
+                ret
+            }))
+            .collect::<Vec<_>>();
+        
+        workers.into_iter()
+            .flat_map(|worker| worker.join().expect("slow5 scanning thread panicked"))
+            .collect()
+    })
+}
+
 pub fn gen_pore_mux_map(scan_data_fpath: &Path) -> HashMap<(u32, u8), PoreMuxStats> {
     let mut ret = HashMap::new();
     
@@ -93,13 +505,11 @@ pub fn gen_pore_mux_map(scan_data_fpath: &Path) -> HashMap<(u32, u8), PoreMuxSta
             pore_muxs.muxs.push(MuxStat {
                 secs_start,
                 pore_state: PoreState::Alive,
-                ..Default::default()
             });
         } else {
             pore_muxs.muxs.push(MuxStat {
                 secs_start,
                 pore_state: PoreState::Dead,
-                ..Default::default()
             });
         }
     }
@@ -107,101 +517,240 @@ pub fn gen_pore_mux_map(scan_data_fpath: &Path) -> HashMap<(u32, u8), PoreMuxSta
     ret
 }
 
-pub fn get_last_read<'a>(mut pore_mux_map: HashMap<(u32, u8), PoreMuxStats<'a>>, read_timestamps: &'a Vec<ReadTimestamp>, pore_state: PoreState) -> Vec<&'a String> {
-    let mut ret = Vec::new();
-    
-    for ts in read_timestamps.iter() {
-        let pore_muxs = pore_mux_map.get_mut(&(ts.channel, ts.pore));
-        if pore_muxs.is_none() { continue; }
-        let pore_muxs = pore_muxs.unwrap();
-        
-        for i in pore_muxs.last_entry..pore_muxs.muxs.len() {
-            let muxstat = pore_muxs.muxs.get_mut(i).expect("error indexing channel_muxs");
-            
-            if ts.secs_start < muxstat.secs_start {
-                if pore_state != muxstat.pore_state {
-                    break;
-                }
-            } else {
-                pore_muxs.last_entry = i+1;
-                continue;
-            }
-            
-            muxstat.read_id = Some(&ts.read_id);
-            muxstat.read_secs_start = ts.secs_start;
-            
-            pore_muxs.last_entry = i;
-            break;
+/// A scan paired with a read. Both are referred to by index, so results can be kept
+/// next to the pore map and read timestamps they were matched from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Match {
+    /// index into the read timestamps
+    pub read: usize,
+    pub channel: u32,
+    pub pore: u8,
+    /// index into the pore's `muxs`
+    pub scan: usize,
+}
+
+/// What `match_each` pairs up, see `BadReadQuery` for the meaning of each field.
+pub(crate) struct MatchParams<'a> {
+    pub(crate) pore_states: &'a [PoreState],
+    pub(crate) read_mode: ReadMode,
+    pub(crate) reads_per_scan: usize,
+    pub(crate) scan_window: ValueRange,
+    pub(crate) max_gap: Option<f64>,
+    pub(crate) channels: Option<&'a HashSet<u32>>,
+}
+
+impl<'a> MatchParams<'a> {
+    fn new(pore_states: &'a [PoreState], read_mode: ReadMode) -> Self {
+        MatchParams {
+            pore_states,
+            read_mode,
+            reads_per_scan: 1,
+            scan_window: ValueRange::default(),
+            max_gap: None,
+            channels: None,
         }
     }
     
-    for pore_muxs in pore_mux_map.values() {
-        for muxstat in pore_muxs.muxs.iter() {
-            if let Some(read_id) = muxstat.read_id {
-                ret.push(read_id);
-            }
-        }
+    pub(crate) fn selects_scan(&self, muxstat: &MuxStat) -> bool {
+        self.pore_states.contains(&muxstat.pore_state) && self.scan_window.contains(muxstat.secs_start)
     }
     
-    ret
+    pub(crate) fn selects_pore(&self, channel: u32) -> bool {
+        self.channels.is_none_or(|channels| channels.contains(&channel))
+    }
 }
 
-pub fn get_first_read<'a>(mut pore_mux_map: HashMap<(u32, u8), PoreMuxStats<'a>>, read_timestamps: &'a Vec<ReadTimestamp>, pore_state: PoreState) -> Vec<&'a String> {
-    let mut ret = Vec::new();
-    
-    for ts in read_timestamps.iter().rev() {
-        let pore_muxs = pore_mux_map.get_mut(&(ts.channel, ts.pore));
-        if pore_muxs.is_none() { continue; }
-        let pore_muxs = pore_muxs.unwrap();
+/// Matching state of one pore, its scans in start time order and the reads matched
+/// to each scan with their index, closest to the scan last.
+struct PoreMatches<'a, T> {
+    muxs: &'a [MuxStat],
+    by_time: Vec<usize>,
+    reads: Vec<Vec<(usize, T)>>,
+}
+
+impl<'a, T> PoreMatches<'a, T> {
+    fn new(pore_muxs: &'a PoreMuxStats) -> Self {
+        let muxs = &pore_muxs.muxs[..];
+        let mut by_time = (0..muxs.len()).collect::<Vec<usize>>();
+        by_time.sort_by(|a, b| muxs[*a].secs_start.total_cmp(&muxs[*b].secs_start));
         
-        for i in (0..(pore_muxs.muxs.len() - pore_muxs.last_entry)).rev() {
-            let muxstat = pore_muxs.muxs.get_mut(i).expect("error indexing channel_muxs");
-            
-            if ts.secs_start > muxstat.secs_start {
-                if pore_state != muxstat.pore_state {
-                    break;
-                }
-            } else {
-                pore_muxs.last_entry = i+1;
-                continue;
+        PoreMatches { muxs, by_time, reads: muxs.iter().map(|_| Vec::new()).collect() }
+    }
+    
+    /// Number of scans that started before `secs`, or at it too when `inclusive`.
+    fn scans_before(&self, secs: f64, inclusive: bool) -> usize {
+        self.by_time.partition_point(|scan| match inclusive {
+            true => self.muxs[*scan].secs_start <= secs,
+            false => self.muxs[*scan].secs_start < secs,
+        })
+    }
+}
+
+pub fn get_last_read<'a>(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, read_timestamps: &'a [ReadTimestamp], pore_state: PoreState) -> Vec<&'a ReadId> {
+    match_reads(pore_mux_map, read_timestamps, pore_state, ReadMode::Last)
+        .iter()
+        .map(|m| &read_timestamps[m.read].read_id)
+        .collect()
+}
+
+pub fn get_first_read<'a>(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, read_timestamps: &'a [ReadTimestamp], pore_state: PoreState) -> Vec<&'a ReadId> {
+    match_reads(pore_mux_map, read_timestamps, pore_state, ReadMode::First)
+        .iter()
+        .map(|m| &read_timestamps[m.read].read_id)
+        .collect()
+}
+
+/// Pairs every scan in `pore_state` with the last read on its pore before it
+/// (`ReadMode::Last`) or the first read after it (`ReadMode::First`). Each read
+/// is located with a binary search over its pore's scans, so neither the reads nor
+/// the scans need to be sorted beforehand. Reads must start strictly before or
+/// after their scan. Of reads starting at the same time the later one in
+/// `read_timestamps` is the last read, the earlier one the first read.
+///
+/// Matches are ordered by channel, pore and scan start time.
+pub fn match_reads(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, read_timestamps: &[ReadTimestamp], pore_state: PoreState, read_mode: ReadMode) -> Vec<Match> {
+    match_each(pore_mux_map, read_timestamps, &MatchParams::new(&[pore_state], read_mode)).0
+        .into_iter()
+        .map(|(m, _)| m)
+        .collect()
+}
+
+/// `match_reads` over a stream of reads, e.g. from `stream_read_timestamps`. Only
+/// the reads currently matched to a scan are kept, and returned with their match;
+/// `Match::read` is the position in the stream.
+pub fn match_read_stream<I: IntoIterator<Item = ReadTimestamp>>(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, reads: I, pore_state: PoreState, read_mode: ReadMode) -> Vec<(Match, ReadTimestamp)> {
+    match_each(pore_mux_map, reads, &MatchParams::new(&[pore_state], read_mode)).0
+}
+
+pub(crate) fn match_each<T: Borrow<ReadTimestamp>, I: IntoIterator<Item = T>>(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, reads: I, params: &MatchParams) -> (Vec<(Match, T)>, QueryStats) {
+    let mut stats = QueryStats::default();
+    let mut read_pores = HashSet::new();
+    
+    let mut pores = pore_mux_map.iter()
+        .filter(|((channel, _), _)| params.selects_pore(*channel))
+        .map(|(key, pore_muxs)| (*key, PoreMatches::new(pore_muxs)))
+        .collect::<HashMap<(u32, u8), PoreMatches<T>>>();
+    
+    // whether `a` lies closer to the scan than `b`; of reads starting together the
+    // later one wins for `Last` and the earlier one for `First`
+    let closer = |a: f64, b: f64| match params.read_mode {
+        ReadMode::Last => a >= b,
+        ReadMode::First => a < b,
+    };
+    
+    for (read, item) in reads.into_iter().enumerate() {
+        let ts = item.borrow();
+        stats.reads += 1;
+        if read_pores.insert((ts.channel, ts.pore)) && !pore_mux_map.contains_key(&(ts.channel, ts.pore)) {
+            stats.pores.reads_only += 1;
+        }
+        let Some(pore) = pores.get_mut(&(ts.channel, ts.pore)) else {
+            if !pore_mux_map.contains_key(&(ts.channel, ts.pore)) {
+                stats.reads_without_scans += 1;
             }
-            
-            muxstat.read_id = Some(&ts.read_id);
-            muxstat.read_secs_start = ts.secs_start;
-            
-            pore_muxs.last_entry = i;
-            break;
+            continue;
+        };
+        
+        // a read starting exactly with a scan is neither before nor after it
+        let i = match params.read_mode {
+            ReadMode::Last => pore.scans_before(ts.secs_start, true),
+            ReadMode::First => match pore.scans_before(ts.secs_start, false) {
+                0 => continue,
+                scans_before => scans_before - 1,
+            },
+        };
+        let Some(&scan) = pore.by_time.get(i) else { continue; };
+        let muxstat = &pore.muxs[scan];
+        if !params.selects_scan(muxstat) { continue; }
+        if params.max_gap.is_some_and(|max_gap| (ts.secs_start - muxstat.secs_start).abs() > max_gap) { continue; }
+        
+        let matched = &mut pore.reads[scan];
+        let pos = matched.partition_point(|(_, other)| closer(ts.secs_start, other.borrow().secs_start));
+        if matched.len() == params.reads_per_scan {
+            if pos == 0 { continue; }
+            matched.remove(0);
+            matched.insert(pos - 1, (read, item));
+        } else {
+            matched.insert(pos, (read, item));
         }
     }
     
-    for pore_muxs in pore_mux_map.values() {
-        for muxstat in pore_muxs.muxs.iter() {
-            if let Some(read_id) = muxstat.read_id {
-                ret.push(read_id);
+    let mut pores = pores.into_iter().collect::<Vec<_>>();
+    pores.sort_by_key(|(key, _)| *key);
+    
+    let mut ret = Vec::new();
+    for ((channel, pore), mut pore_matches) in pores {
+        for scan in pore_matches.by_time.iter().copied() {
+            let muxstat = &pore_matches.muxs[scan];
+            if params.selects_scan(muxstat) {
+                let counts = stats.scans_mut(muxstat.pore_state);
+                if pore_matches.reads[scan].is_empty() {
+                    counts.unmatched += 1;
+                } else {
+                    counts.matched += 1;
+                }
+            }
+            
+            let mut matched = std::mem::take(&mut pore_matches.reads[scan]);
+            // reads of a scan in the order they were seen
+            matched.sort_by_key(|(read, _)| *read);
+            for (read, item) in matched {
+                ret.push((Match { read, channel, pore, scan }, item));
             }
         }
     }
     
-    ret
+    stats.matches = ret.len();
+    stats.pores.scans_only = pore_mux_map.keys().filter(|key| !read_pores.contains(key)).count();
+    stats.pores.both = read_pores.len() - stats.pores.reads_only;
+    
+    (ret, stats)
 }
 
-pub fn filter_reads(read_ids_fpath: &Path, slow5_fpath: &Path, filter_mode: FilterMode) -> Vec<String> {
-    let mut ret = Vec::new();
+pub fn filter_reads(read_ids_fpath: &Path, slow5_fpath: &Path, filter: &ReadFilter) -> Vec<String> {
+    let read_ids = load_read_ids(read_ids_fpath, &ReadIdNormaliser::default()).expect("could not load read_ids").read_ids;
     
-    let slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
+    partition_reads(Some(&read_ids), slow5_fpath, filter, false).passed
+}
+
+/// Splits reads into those matching `filter` and the rest. Without a read_id list
+/// every record is streamed from the slow5 in file order. Read_ids that are not in
+/// the slow5 are collected into `missing`, or panic straight away when `strict`.
+pub fn partition_reads(read_ids: Option<&[String]>, slow5_fpath: &Path, filter: &ReadFilter, strict: bool) -> FilteredReads {
+    let mut ret = FilteredReads::default();
     
-    for read_id in read_to_string(read_ids_fpath).unwrap().lines() {
-        let rec = slow5.get_record(read_id).expect("invalid read_id provided");
-        let channel = rec.get_aux_field::<&str>("channel_number").expect("could not load aux_field `channel_number`");
-        let channel = channel.parse::<u32>().expect("could not parse channel_number as u32");
-        
-        match filter_mode {
-            FilterMode::Odd => {
-                if channel % 2 != 0 { ret.push(read_id.into()); }
-            },
-            FilterMode::Even => {
-                if channel % 2 == 0 { ret.push(read_id.into()); }
-            },
+    let mut slow5 = FileReader::open(slow5_fpath).expect("could not open slow5");
+    let end_reason_labels = end_reason_labels(&slow5);
+    
+    let push = |ret: &mut FilteredReads, read_id: String, rec: &Record| {
+        if filter.matches(&ReadInfo::from_record(rec, &end_reason_labels)) {
+            ret.passed.push(read_id);
+        } else {
+            ret.failed.push(read_id);
+        }
+    };
+    
+    match read_ids {
+        Some(read_ids) => {
+            for read_id in read_ids.iter() {
+                match slow5.get_record(read_id.as_str()) {
+                    Ok(rec) => push(&mut ret, read_id.clone(), &rec),
+                    Err(_) if !strict => ret.missing.push(read_id.clone()),
+                    Err(_) => panic!("invalid read_id provided: {}", read_id),
+                }
+            }
+        }
+        None => {
+            for rec in slow5.records() {
+                if rec.is_err() {
+                    log_warn!("error reading record {:?}, skipping...", rec.err());
+                    continue;
+                }
+                let rec = rec.unwrap();
+                
+                let read_id = String::from_utf8(rec.read_id().to_vec()).expect("could not get read_id from rec");
+                push(&mut ret, read_id, &rec);
+            }
         }
     }
 
@@ -0,0 +1,104 @@
+//! Pore lifetimes from the mux scan timeline. A pore dies at the first Dead scan after
+//! its last Alive one; pores still alive at their last scan are right-censored there.
+
+use std::collections::HashMap;
+
+use crate::{MuxStat, PoreMuxStats, PoreState};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoreLifetime {
+    pub channel: u32,
+    pub mux: u8,
+    pub scans: usize,
+    /// secs, `None` for pores never scanned alive
+    pub last_alive: Option<f64>,
+    /// secs, `None` for censored pores
+    pub death: Option<f64>,
+}
+
+/// One step of a Kaplan-Meier curve, at a time with deaths or censoring.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurvivalPoint {
+    pub secs: f64,
+    pub at_risk: usize,
+    pub deaths: usize,
+    pub censored: usize,
+    pub survival: f64,
+    /// Greenwood standard error of `survival`
+    pub std_err: f64,
+}
+
+impl PoreLifetime {
+    fn from_muxs(channel: u32, mux: u8, muxs: &[MuxStat]) -> Self {
+        let last_alive = muxs.iter()
+            .filter(|muxstat| muxstat.pore_state == PoreState::Alive)
+            .map(|muxstat| muxstat.secs_start)
+            .reduce(f64::max);
+        let death = last_alive.and_then(|last_alive| {
+            muxs.iter()
+                .filter(|muxstat| muxstat.pore_state == PoreState::Dead && muxstat.secs_start > last_alive)
+                .map(|muxstat| muxstat.secs_start)
+                .reduce(f64::min)
+        });
+
+        PoreLifetime { channel, mux, scans: muxs.len(), last_alive, death }
+    }
+
+    pub fn is_censored(&self) -> bool {
+        self.last_alive.is_some() && self.death.is_none()
+    }
+
+    /// Time of death, or of censoring. `None` for pores never scanned alive.
+    pub fn lifetime(&self) -> Option<f64> {
+        self.last_alive?;
+        self.death.or(self.last_alive)
+    }
+}
+
+/// Lifetime of every pore in the map, ordered by channel then mux.
+pub fn pore_lifetimes(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>) -> Vec<PoreLifetime> {
+    let mut ret = pore_mux_map.iter()
+        .map(|((channel, mux), pore_muxs)| PoreLifetime::from_muxs(*channel, *mux, &pore_muxs.muxs))
+        .collect::<Vec<PoreLifetime>>();
+    ret.sort_by_key(|lifetime| (lifetime.channel, lifetime.mux));
+
+    ret
+}
+
+/// Kaplan-Meier estimate over the pores that were ever alive. Pores censored at a
+/// death time are counted at risk for it.
+pub fn kaplan_meier(lifetimes: &[PoreLifetime]) -> Vec<SurvivalPoint> {
+    let mut events = lifetimes.iter()
+        .filter_map(|lifetime| Some((lifetime.lifetime()?, lifetime.is_censored())))
+        .collect::<Vec<(f64, bool)>>();
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut ret = Vec::new();
+    let mut at_risk = events.len();
+    let mut survival = 1.0;
+    let mut greenwood = 0.0;
+
+    for group in events.chunk_by(|a, b| a.0 == b.0) {
+        let deaths = group.iter().filter(|(_, censored)| !censored).count();
+        let censored = group.len() - deaths;
+
+        if deaths > 0 {
+            survival *= 1.0 - deaths as f64 / at_risk as f64;
+            if deaths < at_risk {
+                greenwood += deaths as f64 / (at_risk as f64 * (at_risk - deaths) as f64);
+            }
+        }
+        ret.push(SurvivalPoint {
+            secs: group[0].0,
+            at_risk,
+            deaths,
+            censored,
+            survival,
+            std_err: survival * greenwood.sqrt(),
+        });
+
+        at_risk -= group.len();
+    }
+
+    ret
+}
@@ -0,0 +1,193 @@
+//! Leveled logging to stderr, so stdout stays free for data, plus an optional JSON
+//! lines log for pipeline monitoring. Every event at `Info` or above goes to the
+//! JSON log whatever the stderr level.
+
+use std::{fmt, fs::File, io::{self, BufWriter, IsTerminal, Write}, path::Path, sync::{atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering}, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static JSON_LOG: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+/// Whether a progress line without a newline is on the terminal.
+static PROGRESS_LINE: AtomicBool = AtomicBool::new(false);
+
+impl LogLevel {
+    fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Starts writing events to `fpath` as one JSON object per line.
+pub fn set_json_log(fpath: &Path) -> io::Result<()> {
+    let file = File::create(fpath)?;
+    *JSON_LOG.lock().unwrap() = Some(BufWriter::new(file));
+    Ok(())
+}
+
+/// JSON has no NaN or infinity, so they are written as `null`.
+pub(crate) fn json_num(val: f64) -> String {
+    if val.is_finite() { val.to_string() } else { "null".into() }
+}
+
+pub(crate) fn json_str(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Writes an event with numeric `fields` to the JSON log, if there is one.
+pub fn log_json(level: LogLevel, event: &str, msg: &str, fields: &[(&str, f64)]) {
+    if level > LogLevel::Info { return; }
+    let mut json_log = JSON_LOG.lock().unwrap();
+    let Some(writer) = json_log.as_mut() else { return; };
+
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    let mut line = format!("{{\"ts\":{:.3},\"level\":\"{}\",\"event\":{},\"msg\":{}", ts, level.name(), json_str(event), json_str(msg));
+    for (key, val) in fields.iter() {
+        line.push_str(&format!(",{}:{}", json_str(key), json_num(*val)));
+    }
+    line.push('}');
+
+    // a broken monitoring log shouldn't stop the run
+    let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+}
+
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    let msg = args.to_string();
+    if log_enabled(level) {
+        // clear a half drawn progress line, the next report redraws it
+        if PROGRESS_LINE.swap(false, Ordering::Relaxed) {
+            eprint!("\r\x1b[K");
+        }
+        match level {
+            LogLevel::Error | LogLevel::Warn => eprintln!("{}: {}", level.name(), msg),
+            _ => eprintln!("{}", msg),
+        }
+    }
+    log_json(level, "log", &msg, &[]);
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log($crate::LogLevel::Error, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log($crate::LogLevel::Warn, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log($crate::LogLevel::Info, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log($crate::LogLevel::Debug, format_args!($($arg)*)) };
+}
+
+/// Records-per-second progress of a long scan, shared between scanning threads.
+/// Rewrites one stderr line on a terminal, otherwise logs a line now and then.
+pub struct Progress {
+    label: &'static str,
+    count: AtomicU64,
+    start: Instant,
+    last_report: Mutex<Instant>,
+    interval: Duration,
+    terminal: bool,
+}
+
+impl Progress {
+    pub fn new(label: &'static str) -> Self {
+        let terminal = io::stderr().is_terminal();
+        let now = Instant::now();
+
+        Progress {
+            label,
+            count: AtomicU64::new(0),
+            start: now,
+            last_report: Mutex::new(now),
+            interval: Duration::from_secs(if terminal { 1 } else { 30 }),
+            terminal,
+        }
+    }
+
+    pub fn inc(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        // only one thread reports, the others carry on
+        let Ok(mut last_report) = self.last_report.try_lock() else { return; };
+        if last_report.elapsed() < self.interval { return; }
+        *last_report = Instant::now();
+        drop(last_report);
+
+        self.report(false);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn report(&self, done: bool) {
+        let count = self.count();
+        let secs = self.start.elapsed().as_secs_f64();
+        let rate = if secs > 0.0 { count as f64 / secs } else { 0.0 };
+        let msg = format!("{}: {} records ({:.0} records/s)", self.label, count, rate);
+
+        if log_enabled(LogLevel::Info) {
+            match (self.terminal, done) {
+                (true, false) => {
+                    eprint!("\r{}", msg);
+                    PROGRESS_LINE.store(true, Ordering::Relaxed);
+                }
+                (true, true) => {
+                    eprintln!("\r{}", msg);
+                    PROGRESS_LINE.store(false, Ordering::Relaxed);
+                }
+                (false, _) => eprintln!("{}", msg),
+            }
+        }
+        log_json(LogLevel::Info, if done { "progress_done" } else { "progress" }, self.label, &[
+            ("records", count as f64),
+            ("secs", secs),
+            ("records_per_sec", rate),
+        ]);
+    }
+
+    /// Reports the final count and rate.
+    pub fn finish(&self) {
+        self.report(true);
+    }
+}
@@ -1,18 +1,19 @@
-use std::{env, fs::OpenOptions, io::{BufWriter, Write}, path::Path, process::exit};
+use std::{collections::{HashMap, HashSet}, env, fmt::Display, fs::{File, OpenOptions}, io::{BufWriter, Write}, path::Path, process::exit};
 use bad_reads::*;
 
 #[cfg(test)]
 mod tests;
 
 fn main() {
-    let args = env::args().collect::<Vec<String>>();
+    let args = split_log_opts(env::args().skip(1).collect());
     
-    let subtool = &args[1];
+    let Some(subtool) = args.first() else {
+        eprintln!("usage: bad_reads [-v | -q] [--log-json <path>] <subtool> ...");
+        eprintln!("available subtools: get | filter | features | lifetime | spatial | plot | count | compare-counts | align-qc | basecall-qc | index");
+        exit(1);
+    };
     
-    let mut subtool_args = Vec::new();
-    for i in 2..args.len() {
-        subtool_args.push(args[i].clone());
-    }
+    let subtool_args = args[1..].to_vec();
     
     match subtool.as_str() {
         "get" => {
@@ -21,16 +22,92 @@ fn main() {
         "filter" => {
             filter_main(subtool_args);
         }
+        "features" => {
+            features_main(subtool_args);
+        }
+        "lifetime" => {
+            lifetime_main(subtool_args);
+        }
+        "spatial" => {
+            spatial_main(subtool_args);
+        }
+        "plot" => {
+            plot_main(subtool_args);
+        }
+        "count" => {
+            count_main(subtool_args);
+        }
+        "compare-counts" => {
+            compare_counts_main(subtool_args);
+        }
+        "align-qc" => {
+            align_qc_main(subtool_args);
+        }
+        "basecall-qc" => {
+            basecall_qc_main(subtool_args);
+        }
+        "index" => {
+            index_main(subtool_args);
+        }
         _ => {
-            println!("available subtools: get | filter");
+            eprintln!("available subtools: get | filter | features | lifetime | spatial | plot | count | compare-counts | align-qc | basecall-qc | index");
             exit(1);
         }
     }
 }
 
+/// Takes the logging options out of the args, they are accepted before or after the subtool.
+fn split_log_opts(args: Vec<String>) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut level = LogLevel::Info;
+    
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" => level = LogLevel::Debug,
+            "-q" => level = LogLevel::Warn,
+            "--log-json" => {
+                let Some(log_fpath) = args.next() else {
+                    eprintln!("missing value for option {}", arg);
+                    exit(1);
+                };
+                if let Err(err) = set_json_log(Path::new(&log_fpath)) {
+                    eprintln!("could not open json log {}: {}", log_fpath, err);
+                    exit(1);
+                }
+            }
+            _ => ret.push(arg),
+        }
+    }
+    set_log_level(level);
+    
+    ret
+}
+
 fn get_main(args: Vec<String>) {
+    let (args, opts) = split_opts(
+        args,
+        &["-t", "--memory", "-k", "--scan-window", "--max-gap", "--channels", "--controls", "--controls-per-read", "--control-window", "--control-length", "--seed"],
+        &["--full-decode", "--no-cache"],
+    );
+    
     if args.len() != 5 {
-        println!("usage: bad_reads get <slow5_file path> <scan_data_file path> <out_file path> <pore_state> <read_mode>");
+        eprintln!("usage: bad_reads get <slow5_file path> <scan_data_file path> <out_file path> <pore_state,..> <read_mode> [options]");
+        eprintln!("options:");
+        eprintln!("  -t <threads>            number of threads decoding the slow5 file (default: 1)");
+        eprintln!("  --full-decode           decode whole records, including the raw signal, with slow5lib");
+        eprintln!("  --no-cache              neither read nor write the <slow5_file>.timestamps cache");
+        eprintln!("  --memory <MiB>          sort timestamps in runs of at most this size, spilled to TMPDIR");
+        eprintln!("  -k <reads>              number of reads to take per scan (default: 1)");
+        eprintln!("  --scan-window <min:max> only use scans starting within the window (secs)");
+        eprintln!("  --max-gap <secs>        skip reads further than this from their scan");
+        eprintln!("  --channels <channel,..> only use scans on the given channels");
+        eprintln!("  --controls <path>       write a labelled tsv of the reads and control reads from pores alive");
+        eprintln!("                          at the scans either side of them, matched on start time, not with --memory");
+        eprintln!("  --controls-per-read <n> control reads per read (default: 1)");
+        eprintln!("  --control-window <secs> largest start time difference of a control (default: 300)");
+        eprintln!("  --control-length <frac> largest length difference of a control, as a fraction of the read's");
+        eprintln!("  --seed <n>              seed for sampling controls (default: 0)");
         exit(1);
     }
     
@@ -41,109 +118,1047 @@ fn get_main(args: Vec<String>) {
     let pore_state_arg = &args[3];
     let read_mode_arg = &args[4];
     
-    let pore_state = match pore_state_arg.as_str() {
-        "dead" => PoreState::Dead,
-        "alive" => PoreState::Alive,
-        _ => {
-            println!("valid pore_states: <dead> | <alive>");
-            exit(1);
+    let mut pore_states = Vec::new();
+    for pore_state in pore_state_arg.split(',') {
+        match pore_state {
+            "dead" => pore_states.push(PoreState::Dead),
+            "alive" => pore_states.push(PoreState::Alive),
+            _ => {
+                eprintln!("valid pore_states: <dead> | <alive> | <dead,alive>");
+                exit(1);
+            }
         }
-    };
+    }
     
     let read_mode = match read_mode_arg.as_str() {
         "first" => ReadMode::First,
         "last" => ReadMode::Last,
         _ => {
-            println!("valid modes: <first> | <last>");
+            eprintln!("valid modes: <first> | <last>");
             exit(1);
         }
     };
     
+    let scan_opts = ScanOptions {
+        threads: parse_threads_opt(&opts),
+        meta_only: !opts.contains_key("--full-decode"),
+        cache: if opts.contains_key("--no-cache") { CacheMode::Off } else { CacheMode::ReadWrite },
+        memory_budget: parse_memory_opt(&opts),
+    };
+    
+    let mut query = BadReadQuery::new()
+        .sources(slow5_fpath, scan_data_fpath)
+        .scan_options(scan_opts)
+        .pore_states(&pore_states)
+        .mode(read_mode);
+    
+    if let Some(k) = opts.get("-k") {
+        match k.parse::<usize>() {
+            Ok(k) if k > 0 => query = query.reads_per_scan(k),
+            _ => {
+                eprintln!("invalid read count {}", k);
+                exit(1);
+            }
+        }
+    }
+    if let Some(range) = parse_range_opt(&opts, "--scan-window") {
+        query = query.scan_window(range);
+    }
+    if let Some(max_gap) = opts.get("--max-gap") {
+        match max_gap.parse::<f64>() {
+            Ok(max_gap) if max_gap >= 0.0 => query = query.max_gap(max_gap),
+            _ => {
+                eprintln!("invalid max gap {}", max_gap);
+                exit(1);
+            }
+        }
+    }
+    if let Some(channels) = opts.get("--channels") {
+        match channels.split(',').map(|channel| channel.parse::<u32>()).collect::<Result<Vec<u32>, _>>() {
+            Ok(channels) => query = query.channels(channels),
+            Err(_) => {
+                eprintln!("invalid channel list, expected e.g. <1,2>");
+                exit(1);
+            }
+        }
+    }
+    
+    let controls_fpath = opts.get("--controls").map(Path::new);
+    if controls_fpath.is_some() && opts.contains_key("--memory") {
+        eprintln!("--controls holds the run in memory and can't be used with --memory");
+        exit(1);
+    }
+    let mut control_opts = ControlOptions::default();
+    if let Some(per_read) = opts.get("--controls-per-read") {
+        match per_read.parse::<usize>() {
+            Ok(per_read) if per_read > 0 => control_opts.per_read = per_read,
+            _ => {
+                eprintln!("invalid control count {}", per_read);
+                exit(1);
+            }
+        }
+    }
+    if let Some(window) = opts.get("--control-window") {
+        match window.parse::<f64>() {
+            Ok(window) if window >= 0.0 => control_opts.time_tolerance = window,
+            _ => {
+                eprintln!("invalid control window {}", window);
+                exit(1);
+            }
+        }
+    }
+    if let Some(length) = opts.get("--control-length") {
+        match length.parse::<f64>() {
+            Ok(length) if length >= 0.0 => control_opts.length_tolerance = Some(length),
+            _ => {
+                eprintln!("invalid control length tolerance {}, expected a fraction", length);
+                exit(1);
+            }
+        }
+    }
+    if let Some(seed) = opts.get("--seed") {
+        match seed.parse::<u64>() {
+            Ok(seed) => control_opts.seed = seed,
+            Err(_) => {
+                eprintln!("invalid seed {}", seed);
+                exit(1);
+            }
+        }
+    }
+    
     if !scan_data_fpath.exists() {
-        println!("invalid scan_data path");
+        eprintln!("invalid scan_data path");
         exit(1);
     }
     
     if !slow5_fpath.exists() {
-        println!("invalid slow5 path");
+        eprintln!("invalid slow5 path");
         exit(1);
     }
     
-    let out_file = OpenOptions::new()
-        .create_new(true)
-        .write(true)
-        .append(true)
-        .open(out_fpath)
-        .expect("could not open out file");
-        
-    println!("reading mux scan data...");
+    let out_file = create_out_file(out_fpath);
+    let controls_file = controls_fpath.map(create_out_file);
+    
+    log_info!("fetching reads...");
+    let (result, controls) = match controls_file {
+        Some(_) => {
+            let (result, controls) = query.run_with_controls(&control_opts);
+            (result, Some(controls))
+        }
+        None => (query.run(), None),
+    };
+    print_query_stats(&result.stats);
+    
+    log_info!("writing read_ids into file...");
+    write_read_ids(out_file, &result.read_ids());
+    
+    if let (Some(controls_file), Some(controls)) = (controls_file, controls) {
+        log_info!("controls: {} from {} eligible reads, {} reads with fewer than {}", controls.reads.len(), controls.eligible, controls.short, control_opts.per_read);
+        write_controls(controls_file, &result, &controls);
+    }
+    
+    log_info!("all done!");
+}
+
+/// One row per read, labelled `case` or `control`, with the read each control was matched to.
+fn write_controls(out_file: File, result: &QueryResult, controls: &ControlSet) {
+    let mut out_file = BufWriter::new(out_file);
+    
+    writeln!(out_file, "read_id\tlabel\tcase_read_id").expect("error writing to controls file");
+    for read_id in result.read_ids() {
+        writeln!(out_file, "{}\tcase\t{}", read_id, read_id).expect("error writing to controls file");
+    }
+    for control in controls.reads.iter() {
+        writeln!(out_file, "{}\tcontrol\t{}", control.read_id, control.case).expect("error writing to controls file");
+    }
+    out_file.flush().expect("error writing to controls file");
+}
+
+fn print_query_stats(stats: &QueryStats) {
+    log_info!("reads: {} ({} on pores without scans)", stats.reads, stats.reads_without_scans);
+    for (name, counts) in [("dead", stats.dead_scans), ("alive", stats.alive_scans)] {
+        if counts.matched + counts.unmatched > 0 {
+            log_info!("{} scans: {} matched, {} unmatched", name, counts.matched, counts.unmatched);
+        }
+    }
+    log_info!("matched reads: {}", stats.matches);
+    log_info!("pores: {} with scans and reads, {} with scans only, {} with reads only", stats.pores.both, stats.pores.scans_only, stats.pores.reads_only);
+}
+
+fn lifetime_main(args: Vec<String>) {
+    let (args, _) = split_opts(args, &[], &[]);
+    
+    if args.len() != 3 {
+        eprintln!("usage: bad_reads lifetime <scan_data_file path> <pores_out path> <survival_out path>");
+        eprintln!("writes each pore's time of death (first dead scan after its last alive one) and a");
+        eprintln!("Kaplan-Meier survival curve as tsv, pores alive at their last scan are censored there");
+        exit(1);
+    }
+    
+    let scan_data_fpath = Path::new(&args[0]);
+    if !scan_data_fpath.exists() {
+        eprintln!("invalid scan_data path");
+        exit(1);
+    }
+    
+    let mut pores_file = BufWriter::new(create_out_file(Path::new(&args[1])));
+    let mut survival_file = BufWriter::new(create_out_file(Path::new(&args[2])));
+    
+    let lifetimes = pore_lifetimes(&gen_pore_mux_map(scan_data_fpath));
+    let curve = kaplan_meier(&lifetimes);
+    
+    let secs_or_na = |secs: Option<f64>| secs.map_or("NA".to_string(), |secs| secs.to_string());
+    writeln!(pores_file, "channel\tmux\tscans\tlast_alive\tdeath\tlifetime\tcensored").expect("error writing to pores file");
+    for lifetime in lifetimes.iter() {
+        writeln!(
+            pores_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            lifetime.channel,
+            lifetime.mux,
+            lifetime.scans,
+            secs_or_na(lifetime.last_alive),
+            secs_or_na(lifetime.death),
+            secs_or_na(lifetime.lifetime()),
+            lifetime.is_censored() as u8,
+        ).expect("error writing to pores file");
+    }
+    pores_file.flush().expect("error writing to pores file");
+    
+    writeln!(survival_file, "secs\tat_risk\tdeaths\tcensored\tsurvival\tstd_err").expect("error writing to survival file");
+    for point in curve.iter() {
+        writeln!(survival_file, "{}\t{}\t{}\t{}\t{:.6}\t{:.6}", point.secs, point.at_risk, point.deaths, point.censored, point.survival, point.std_err)
+            .expect("error writing to survival file");
+    }
+    survival_file.flush().expect("error writing to survival file");
+    
+    let never_alive = lifetimes.iter().filter(|lifetime| lifetime.last_alive.is_none()).count();
+    let censored = lifetimes.iter().filter(|lifetime| lifetime.is_censored()).count();
+    log_info!("pores: {} died, {} censored, {} never alive", lifetimes.len() - never_alive - censored, censored, never_alive);
+}
+
+fn spatial_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--layout", "--slow5", "--permutations", "--seed"], &[]);
+    
+    if args.len() != 3 {
+        eprintln!("usage: bad_reads spatial <scan_data_file path> <grid_out path> <moran_out path> [options]");
+        eprintln!("writes pore deaths per channel with its flowcell position, and Moran's I of dead pores");
+        eprintln!("over neighbouring channels for the whole run and for every round of scans");
+        eprintln!("options:");
+        eprintln!("  --layout <layout>       flowcell: minion | flongle | promethion");
+        eprintln!("  --slow5 <path>          take the flowcell from this slow5 file's header instead");
+        eprintln!("  --permutations <n>      permutations for the p-values (default: 999)");
+        eprintln!("  --seed <n>              seed for the permutations (default: 0)");
+        exit(1);
+    }
+    
+    let scan_data_fpath = Path::new(&args[0]);
+    if !scan_data_fpath.exists() {
+        eprintln!("invalid scan_data path");
+        exit(1);
+    }
+    let slow5_fpath = opts.get("--slow5").map(Path::new);
+    if slow5_fpath.is_some_and(|slow5_fpath| !slow5_fpath.exists()) {
+        eprintln!("invalid slow5 path");
+        exit(1);
+    }
+    let layout = parse_layout_opt(&opts, slow5_fpath);
+    
+    let permutations = match opts.get("--permutations").map(|n| n.parse::<usize>()) {
+        None => 999,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("invalid permutation count");
+            exit(1);
+        }
+    };
+    let seed = match opts.get("--seed").map(|seed| seed.parse::<u64>()) {
+        None => 0,
+        Some(Ok(seed)) => seed,
+        Some(Err(_)) => {
+            eprintln!("invalid seed");
+            exit(1);
+        }
+    };
+    
+    let mut grid_file = BufWriter::new(create_out_file(Path::new(&args[1])));
+    let mut moran_file = BufWriter::new(create_out_file(Path::new(&args[2])));
+    
     let pore_mux_map = gen_pore_mux_map(scan_data_fpath);
+    let deaths = channel_deaths(&pore_mux_map, layout);
+    
+    let fixed_or_na = |val: f64, digits: usize| if val.is_nan() { "NA".to_string() } else { format!("{:.*}", digits, val) };
+    writeln!(grid_file, "channel\trow\tcol\tpores\tdied\tnever_alive\tdead_frac").expect("error writing to grid file");
+    for channel in deaths.iter() {
+        writeln!(
+            grid_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            channel.channel,
+            channel.pos.row,
+            channel.pos.col,
+            channel.pores,
+            channel.died,
+            channel.never_alive,
+            fixed_or_na(channel.dead_frac(), 4),
+        ).expect("error writing to grid file");
+    }
+    grid_file.flush().expect("error writing to grid file");
+    
+    let write_test = |moran_file: &mut BufWriter<File>, scope: &str, secs: &str, test: &MoranTest| {
+        writeln!(moran_file, "{}\t{}\t{}\t{}\t{}\t{}", scope, secs, test.channels, fixed_or_na(test.morans_i, 6), fixed_or_na(test.expected, 6), fixed_or_na(test.p_value, 6))
+            .expect("error writing to moran file");
+    };
+    
+    writeln!(moran_file, "scope\tsecs\tchannels\tmorans_i\texpected\tp_value").expect("error writing to moran file");
+    let values = deaths.iter().map(|channel| (channel.channel, channel.dead_frac())).collect::<Vec<(u32, f64)>>();
+    let run_test = moran_test(layout, &values, permutations, seed);
+    write_test(&mut moran_file, "run", "NA", &run_test);
+    for round in scan_rounds(&pore_mux_map) {
+        let test = moran_test(layout, &round.values, permutations, seed);
+        write_test(&mut moran_file, &format!("scan_{}", round.round + 1), &round.secs.to_string(), &test);
+    }
+    moran_file.flush().expect("error writing to moran file");
+    
+    log_info!("dead pores over {} channels: Moran's I {:.4} (expected {:.4}), p = {:.4}", run_test.channels, run_test.morans_i, run_test.expected, run_test.p_value);
+}
+
+fn plot_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--slow5", "--selected", "--layout", "-t", "--id-rules", "--list-format"], &["--no-cache"]);
+    
+    if args.len() != 2 {
+        eprintln!("usage: bad_reads plot <scan_data_file path> <out_dir path> [options]");
+        eprintln!("writes svg flowcell heatmaps of the pore states at every scan (scan_<n>.svg), and");
+        eprintln!("with a slow5 file of the reads (reads.svg) and selected reads (selected.svg) per channel");
+        eprintln!("options:");
+        eprintln!("  --slow5 <path>          slow5 file to count reads from, its header also gives the flowcell");
+        eprintln!("  --selected <path>       read_ids to count, e.g. get or filter output (needs --slow5)");
+        eprintln!("  --layout <layout>       flowcell: minion | flongle | promethion (default: from slow5 header)");
+        eprintln!("  -t <threads>            number of threads decoding the slow5 file (default: 1)");
+        eprintln!("  --no-cache              neither read nor write the <slow5_file>.timestamps cache");
+        eprintln!("  --id-rules <rule,..>    read_id normalisation: trim,marker,field,prefix | all | none (default: all)");
+        eprintln!("  --list-format <format>  read_ids format: text | fastq | sam | bam | paf | summary (default: detected)");
+        exit(1);
+    }
+    
+    let scan_data_fpath = Path::new(&args[0]);
+    let out_dir = Path::new(&args[1]);
+    let slow5_fpath = opts.get("--slow5").map(Path::new);
+    let selected_fpath = opts.get("--selected").map(Path::new);
+    
+    if !scan_data_fpath.exists() {
+        eprintln!("invalid scan_data path");
+        exit(1);
+    }
+    if slow5_fpath.is_some_and(|slow5_fpath| !slow5_fpath.exists()) {
+        eprintln!("invalid slow5 path");
+        exit(1);
+    }
+    if selected_fpath.is_some_and(|selected_fpath| !selected_fpath.exists()) {
+        eprintln!("invalid selected path");
+        exit(1);
+    }
+    if selected_fpath.is_some() && slow5_fpath.is_none() {
+        eprintln!("--selected needs --slow5 to place reads on channels");
+        exit(1);
+    }
+    let layout = parse_layout_opt(&opts, slow5_fpath);
+    
+    std::fs::create_dir_all(out_dir).expect("could not create out dir");
+    let write_svg = |name: &str, svg: String| {
+        let mut out_file = create_out_file(&out_dir.join(name));
+        out_file.write_all(svg.as_bytes()).expect("error writing svg");
+    };
+    
+    let rounds = scan_rounds(&gen_pore_mux_map(scan_data_fpath));
+    for round in rounds.iter() {
+        write_svg(&format!("scan_{}.svg", round.round + 1), scan_round_svg(layout, round));
+    }
+    log_info!("plotted {} scans", rounds.len());
+    
+    if let Some(slow5_fpath) = slow5_fpath {
+        let scan_opts = ScanOptions {
+            threads: parse_threads_opt(&opts),
+            cache: if opts.contains_key("--no-cache") { CacheMode::Off } else { CacheMode::ReadWrite },
+            ..Default::default()
+        };
+        let read_metas = load_read_meta(slow5_fpath, &scan_opts);
+        write_svg("reads.svg", channel_counts_svg(layout, "reads per channel", &reads_per_channel(&read_metas, None)));
+        
+        if let Some(selected_fpath) = selected_fpath {
+            let read_ids = load_read_list(selected_fpath, parse_list_format_opt(&opts), &parse_id_rules_opt(&opts));
+            let selected = read_ids.iter().map(|read_id| ReadId::from(read_id.as_str())).collect::<HashSet<ReadId>>();
+            let counts = reads_per_channel(&read_metas, Some(&selected));
+            
+            let placed = counts.values().sum::<usize>();
+            if placed < selected.len() {
+                log_warn!("{} selected read_ids were not found in the slow5 file", selected.len() - placed);
+            }
+            write_svg("selected.svg", channel_counts_svg(layout, "selected reads per channel", &counts));
+        }
+    }
+    
+    log_info!("all done!");
+}
+
+fn count_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--min-mapq", "--min-aligned-len", "--gene-map", "--id-rules", "--list-format"], &["--all-alignments"]);
+    
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("usage: bad_reads count [read_ids path] <alignments path> <out_file path> [options]");
+        eprintln!("writes `count name` lines of the reads aligned to every reference, as compare-counts reads them");
+        eprintln!("alignments can be sam, bam or paf; without a read_ids file every aligned read is counted");
+        eprintln!("options:");
+        eprintln!("  --all-alignments        also count secondary and supplementary alignments");
+        eprintln!("  --min-mapq <n>          skip alignments with a lower MAPQ (default: 0)");
+        eprintln!("  --min-aligned-len <n>   skip alignments covering fewer read bases (default: 0)");
+        eprintln!("  --gene-map <path>       count per gene from `reference gene` lines instead of per reference");
+        eprintln!("  --id-rules <rule,..>    read_id normalisation: trim,marker,field,prefix | all | none (default: all)");
+        eprintln!("  --list-format <format>  read_ids format: text | fastq | sam | bam | paf | summary (default: detected)");
+        exit(1);
+    }
+    
+    let (read_ids_fpath, args) = match args.len() {
+        3 => (Some(Path::new(&args[0])), &args[1..]),
+        _ => (None, &args[..]),
+    };
+    let align_fpath = Path::new(&args[0]);
+    let out_fpath = Path::new(&args[1]);
+    let gene_map_fpath = opts.get("--gene-map").map(Path::new);
+    
+    let mut count_opts = CountOptions {
+        primary_only: !opts.contains_key("--all-alignments"),
+        ..Default::default()
+    };
+    if let Some(mapq) = opts.get("--min-mapq") {
+        match mapq.parse::<u8>() {
+            Ok(mapq) => count_opts.min_mapq = mapq,
+            Err(_) => {
+                eprintln!("invalid MAPQ {}", mapq);
+                exit(1);
+            }
+        }
+    }
+    if let Some(len) = opts.get("--min-aligned-len") {
+        match len.parse::<u64>() {
+            Ok(len) => count_opts.min_aligned_len = len,
+            Err(_) => {
+                eprintln!("invalid alignment length {}", len);
+                exit(1);
+            }
+        }
+    }
+    
+    if read_ids_fpath.is_some_and(|read_ids_fpath| !read_ids_fpath.exists()) {
+        eprintln!("invalid read_list path");
+        exit(1);
+    }
+    if !align_fpath.exists() {
+        eprintln!("invalid alignments path");
+        exit(1);
+    }
+    if gene_map_fpath.is_some_and(|gene_map_fpath| !gene_map_fpath.exists()) {
+        eprintln!("invalid gene map path");
+        exit(1);
+    }
+    
+    let out_file = create_out_file(out_fpath);
+    count_opts.gene_map = gene_map_fpath.map(load_gene_map);
+    let read_ids = read_ids_fpath.map(|read_ids_fpath| {
+        load_read_list(read_ids_fpath, parse_list_format_opt(&opts), &parse_id_rules_opt(&opts))
+            .iter()
+            .map(|read_id| ReadId::from(read_id.as_str()))
+            .collect::<HashSet<ReadId>>()
+    });
+    
+    log_info!("counting reads...");
+    let counts = count_reads(align_fpath, read_ids.as_ref(), &count_opts);
+    log_info!("{} alignments: {} unmapped, {} filtered", counts.alignments, counts.unmapped, counts.filtered);
+    if counts.unlisted > 0 {
+        log_warn!("{} alignments were to references missing from the gene map", counts.unlisted);
+    }
+    
+    let mut out_file = BufWriter::new(out_file);
+    for (name, count) in counts.counts.iter() {
+        writeln!(out_file, "{}\t{}", count, name).expect("error writing to out file");
+    }
+    out_file.flush().expect("error writing to out file");
+    
+    log_info!("all done!");
+}
+
+fn compare_counts_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--svg", "--count-col", "--q"], &[]);
+    
+    if args.len() != 3 {
+        eprintln!("usage: bad_reads compare-counts <all_counts path> <selected_counts path> <out_file path> [options]");
+        eprintln!("merges per-name counts of all reads and selected reads, names missing from one count 0,");
+        eprintln!("and writes them as tsv with the log2 enrichment, Fisher's exact p-value and BH q-value");
+        eprintln!("options:");
+        eprintln!("  --count-col <0|1>       column holding the counts, the other holds the name (default: 0)");
+        eprintln!("  --svg <path>            also draw a log-log scatter of selected against all reads");
+        eprintln!("  --q <threshold>         highlight names below this q-value in the scatter (default: 0.05)");
+        exit(1);
+    }
+    
+    let all_fpath = Path::new(&args[0]);
+    let selected_fpath = Path::new(&args[1]);
+    let out_fpath = Path::new(&args[2]);
+    let svg_fpath = opts.get("--svg").map(Path::new);
+    
+    let count_col = match opts.get("--count-col").map(String::as_str) {
+        None | Some("0") => 0,
+        Some("1") => 1,
+        Some(_) => {
+            eprintln!("valid count columns: <0> | <1>");
+            exit(1);
+        }
+    };
+    let q_threshold = match opts.get("--q").map(|q| q.parse::<f64>()) {
+        None => 0.05,
+        Some(Ok(q)) if (0.0..=1.0).contains(&q) => q,
+        Some(_) => {
+            eprintln!("invalid q-value threshold");
+            exit(1);
+        }
+    };
+    
+    for (fpath, name) in [(all_fpath, "all_counts"), (selected_fpath, "selected_counts")] {
+        if !fpath.exists() {
+            eprintln!("invalid {} path", name);
+            exit(1);
+        }
+    }
+    
+    let mut out_file = BufWriter::new(create_out_file(out_fpath));
+    let svg_file = svg_fpath.map(create_out_file);
+    
+    let comparisons = compare_counts(&load_counts(all_fpath, count_col), &load_counts(selected_fpath, count_col));
+    
+    writeln!(out_file, "name\tall\tselected\tlog2_ratio\tp_value\tq_value").expect("error writing to out file");
+    for comparison in comparisons.iter() {
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{:.4}\t{:.4e}\t{:.4e}",
+            comparison.name, comparison.all, comparison.selected, comparison.log2_ratio, comparison.p_value, comparison.q_value,
+        ).expect("error writing to out file");
+    }
+    out_file.flush().expect("error writing to out file");
+    
+    if let Some(mut svg_file) = svg_file {
+        let title = format!("{} vs {}", all_fpath.display(), selected_fpath.display());
+        svg_file.write_all(count_scatter_svg(&title, &comparisons, q_threshold).as_bytes()).expect("error writing svg");
+    }
+    
+    let enriched = comparisons.iter().filter(|comparison| comparison.q_value < q_threshold).count();
+    log_info!("{} names, {} with q < {}", comparisons.len(), enriched, q_threshold);
+}
+
+fn align_qc_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--id-rules", "--list-format"], &[]);
+    
+    if args.len() != 4 && args.len() != 5 {
+        eprintln!("usage: bad_reads align-qc <alignments path> <selected path> [controls path] <per_read_out path> <summary_out path>");
+        eprintln!("compares the primary alignments of selected reads against control reads: mapping rate,");
+        eprintln!("MAPQ, identity, aligned and soft-clipped fractions, with Fisher's exact and Mann-Whitney tests");
+        eprintln!("the reads can be a `get --controls` table holding both groups, or two read lists");
+        eprintln!("options:");
+        eprintln!("  --id-rules <rule,..>    read_id normalisation: trim,marker,field,prefix | all | none (default: all)");
+        eprintln!("  --list-format <format>  read_ids format: text | fastq | sam | bam | paf | summary (default: detected)");
+        exit(1);
+    }
+    
+    let align_fpath = Path::new(&args[0]);
+    let selected_fpath = Path::new(&args[1]);
+    let (controls_fpath, args) = match args.len() {
+        5 => (Path::new(&args[2]), &args[3..]),
+        _ => (selected_fpath, &args[2..]),
+    };
+    let per_read_fpath = Path::new(&args[0]);
+    let summary_fpath = Path::new(&args[1]);
+    
+    if !align_fpath.exists() {
+        eprintln!("invalid alignments path");
+        exit(1);
+    }
+    for (fpath, name) in [(selected_fpath, "selected"), (controls_fpath, "controls")] {
+        if !fpath.exists() {
+            eprintln!("invalid {} path", name);
+            exit(1);
+        }
+    }
+    
+    let per_read_file = create_out_file(per_read_fpath);
+    let summary_file = create_out_file(summary_fpath);
+    
+    let both_groups = selected_fpath == controls_fpath;
+    let reads = load_read_group(selected_fpath, "case", both_groups, &opts).into_iter()
+        .map(|read_id| (read_id, ReadGroup::Selected))
+        .chain(load_read_group(controls_fpath, "control", both_groups, &opts).into_iter().map(|read_id| (read_id, ReadGroup::Control)))
+        .collect::<Vec<(String, ReadGroup)>>();
+    
+    log_info!("reading alignments...");
+    let read_qcs = align_qc(align_fpath, &reads);
+    let missing = read_qcs.iter().filter(|qc| qc.records == 0).count();
+    if missing > 0 {
+        log_warn!("{} reads have no alignment records and count as unmapped", missing);
+    }
+    
+    let mut per_read_file = BufWriter::new(per_read_file);
+    writeln!(per_read_file, "{}", ALIGN_QC_TSV_HEADER).expect("error writing to per_read file");
+    for qc in read_qcs.iter() {
+        writeln!(per_read_file, "{}", qc.tsv_row()).expect("error writing to per_read file");
+    }
+    per_read_file.flush().expect("error writing to per_read file");
+    
+    write_metric_comparisons(summary_file, &compare_align_qc(&read_qcs));
+    
+    log_info!("all done!");
+}
+
+fn basecall_qc_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--controls", "--id-rules", "--list-format"], &["--json"]);
+    
+    if args.len() != 3 {
+        eprintln!("usage: bad_reads basecall-qc <sequencing_summary path> <selected path> <out_file path> [options]");
+        eprintln!("compares q-score, pass rate and length of the selected reads against the other reads of");
+        eprintln!("the sequencing summary, with Mann-Whitney and Fisher's exact tests");
+        eprintln!("options:");
+        eprintln!("  --controls <path>       compare against these reads instead, e.g. a `get --controls` table");
+        eprintln!("  --json                  write the report as json instead of tsv");
+        eprintln!("  --id-rules <rule,..>    read_id normalisation: trim,marker,field,prefix | all | none (default: all)");
+        eprintln!("  --list-format <format>  read_ids format: text | fastq | sam | bam | paf | summary (default: detected)");
+        exit(1);
+    }
+    
+    let summary_fpath = Path::new(&args[0]);
+    let selected_fpath = Path::new(&args[1]);
+    let out_fpath = Path::new(&args[2]);
+    let controls_fpath = opts.get("--controls").map(Path::new);
+    
+    for (fpath, name) in [(summary_fpath, "sequencing_summary"), (selected_fpath, "selected")] {
+        if !fpath.exists() {
+            eprintln!("invalid {} path", name);
+            exit(1);
+        }
+    }
+    if controls_fpath.is_some_and(|controls_fpath| !controls_fpath.exists()) {
+        eprintln!("invalid controls path");
+        exit(1);
+    }
+    
+    let out_file = create_out_file(out_fpath);
+    
+    let both_groups = controls_fpath == Some(selected_fpath);
+    let load_group = |fpath: &Path, label: &str| {
+        load_read_group(fpath, label, both_groups, &opts).iter().map(|read_id| ReadId::from(read_id.as_str())).collect::<HashSet<ReadId>>()
+    };
+    let selected = load_group(selected_fpath, "case");
+    let controls = controls_fpath.map(|controls_fpath| load_group(controls_fpath, "control"));
+    
+    log_info!("reading sequencing summary...");
+    let report = compare_basecalls(summary_fpath, &selected, controls.as_ref());
+    log_info!("{} selected and {} other reads", report.selected, report.control);
+    if report.missing > 0 {
+        log_warn!("{} read_ids were not found in the sequencing summary", report.missing);
+    }
+    
+    if opts.contains_key("--json") {
+        let mut out_file = BufWriter::new(out_file);
+        out_file.write_all(report.to_json().as_bytes()).expect("error writing to out file");
+        out_file.flush().expect("error writing to out file");
+    } else {
+        write_metric_comparisons(out_file, &report.comparisons);
+    }
+    
+    log_info!("all done!");
+}
 
-    println!("generating slow5 read timestamps...");
-    let read_timestamps = gen_read_timestamps(slow5_fpath);
+fn index_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["-t"], &["--full-decode"]);
+    
+    if args.len() != 1 {
+        eprintln!("usage: bad_reads index <slow5_file path> [options]");
+        eprintln!("(re)builds the <slow5_file>.timestamps cache used by get");
+        eprintln!("options:");
+        eprintln!("  -t <threads>            number of threads decoding the slow5 file (default: 1)");
+        eprintln!("  --full-decode           decode whole records, including the raw signal, with slow5lib");
+        exit(1);
+    }
     
-    println!("fetching reads...");
-    let bad_reads = match read_mode {
-        ReadMode::First => get_first_read(pore_mux_map, &read_timestamps, pore_state),
-        ReadMode::Last => get_last_read(pore_mux_map, &read_timestamps, pore_state),
+    let slow5_fpath = Path::new(&args[0]);
+    if !slow5_fpath.exists() {
+        eprintln!("invalid slow5 path");
+        exit(1);
+    }
+    
+    let scan_opts = ScanOptions {
+        threads: parse_threads_opt(&opts),
+        meta_only: !opts.contains_key("--full-decode"),
+        cache: CacheMode::Rebuild,
+        ..Default::default()
     };
     
-    println!("writing read_ids into file...");
+    log_info!("generating slow5 read timestamps...");
+    let read_metas = load_read_meta(slow5_fpath, &scan_opts);
+    
+    log_info!("cached {} reads in {}", read_metas.len(), cache_path(slow5_fpath).display());
+}
+
+fn write_metric_comparisons(out_file: File, comparisons: &[MetricComparison]) {
     let mut out_file = BufWriter::new(out_file);
     
-    for bad_read in bad_reads.into_iter() {
-        writeln!(out_file, "{}", bad_read).expect("error writing read_id to out file");
+    writeln!(
+        out_file,
+        "metric\tselected_n\tselected_mean\tselected_median\tselected_q25\tselected_q75\tcontrol_n\tcontrol_mean\tcontrol_median\tcontrol_q25\tcontrol_q75\tp_value",
+    ).expect("error writing summary table");
+    for comparison in comparisons.iter() {
+        let (selected, control) = (&comparison.selected, &comparison.control);
+        writeln!(
+            out_file,
+            "{}\t{}\t{:.4}\t{:.4}\t{:.4}\t{:.4}\t{}\t{:.4}\t{:.4}\t{:.4}\t{:.4}\t{:.4e}",
+            comparison.metric,
+            selected.n, selected.mean, selected.median, selected.q25, selected.q75,
+            control.n, control.mean, control.median, control.q25, control.q75,
+            comparison.p_value,
+        ).expect("error writing summary table");
+    }
+    out_file.flush().expect("error writing summary table");
+}
+
+fn split_opts(args: Vec<String>, value_opts: &[&str], flag_opts: &[&str]) -> (Vec<String>, HashMap<String, String>) {
+    let mut positional = Vec::new();
+    let mut opts = HashMap::new();
+    
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg.len() == 1 {
+            positional.push(arg);
+        } else if value_opts.contains(&arg.as_str()) {
+            let Some(val) = args.next() else {
+                eprintln!("missing value for option {}", arg);
+                exit(1);
+            };
+            opts.insert(arg, val);
+        } else if flag_opts.contains(&arg.as_str()) {
+            opts.insert(arg, String::new());
+        } else {
+            eprintln!("unknown option {}", arg);
+            exit(1);
+        }
     }
     
-    println!("all done!");
+    (positional, opts)
+}
+
+fn parse_threads_opt(opts: &HashMap<String, String>) -> usize {
+    let Some(threads) = opts.get("-t") else {
+        return 1;
+    };
+    match threads.parse::<usize>() {
+        Ok(threads) if threads > 0 => threads,
+        _ => {
+            eprintln!("invalid thread count {}", threads);
+            exit(1);
+        }
+    }
+}
+
+fn parse_memory_opt(opts: &HashMap<String, String>) -> Option<usize> {
+    let memory = opts.get("--memory")?;
+    match memory.parse::<usize>() {
+        Ok(memory) if memory > 0 => Some(memory << 20),
+        _ => {
+            eprintln!("invalid memory budget {}, expected MiB", memory);
+            exit(1);
+        }
+    }
+}
+
+fn parse_range_opt(opts: &HashMap<String, String>, opt: &str) -> Option<ValueRange> {
+    let val = opts.get(opt)?;
+    match ValueRange::parse(val) {
+        Some(range) => Some(range),
+        None => {
+            eprintln!("invalid range for {}: expected <min:max>, <min:> or <:max>", opt);
+            exit(1);
+        }
+    }
+}
+
+/// Layout from --layout, otherwise from the slow5 header.
+fn parse_layout_opt(opts: &HashMap<String, String>, slow5_fpath: Option<&Path>) -> FlowcellLayout {
+    if let Some(layout) = opts.get("--layout") {
+        return match FlowcellLayout::from_name(layout) {
+            Some(layout) => layout,
+            None => {
+                eprintln!("valid layouts: <minion> | <flongle> | <promethion>");
+                exit(1);
+            }
+        };
+    }
+    match slow5_fpath.and_then(read_flowcell_layout) {
+        Some(layout) => layout,
+        None => {
+            eprintln!("could not tell the flowcell from a slow5 header, set it with --layout");
+            exit(1);
+        }
+    }
+}
+
+fn parse_id_rules_opt(opts: &HashMap<String, String>) -> ReadIdNormaliser {
+    let Some(rules) = opts.get("--id-rules") else {
+        return ReadIdNormaliser::default();
+    };
+    match ReadIdNormaliser::from_rules(rules) {
+        Some(normaliser) => normaliser,
+        None => {
+            eprintln!("valid id rules: <trim> | <marker> | <field> | <prefix> | <all> | <none>");
+            exit(1);
+        }
+    }
+}
+
+fn parse_list_format_opt(opts: &HashMap<String, String>) -> Option<ReadListFormat> {
+    let format = opts.get("--list-format")?;
+    match ReadListFormat::from_name(format) {
+        Some(format) => Some(format),
+        None => {
+            eprintln!("valid list formats: <text> | <fastq> | <sam> | <bam> | <paf> | <summary>");
+            exit(1);
+        }
+    }
+}
+
+fn load_read_list(read_ids_fpath: &Path, format: Option<ReadListFormat>, normaliser: &ReadIdNormaliser) -> Vec<String> {
+    let format = format.unwrap_or_else(|| ReadListFormat::detect(read_ids_fpath));
+    let read_id_list = match load_read_ids_as(read_ids_fpath, format, normaliser) {
+        Ok(read_id_list) => read_id_list,
+        Err(err) => {
+            eprintln!("could not load {}: {}", read_ids_fpath.display(), err);
+            exit(1);
+        }
+    };
+    
+    if read_id_list.rewritten > 0 {
+        log_warn!("normalised {} read_id lines in {}", read_id_list.rewritten, read_ids_fpath.display());
+    }
+    
+    read_id_list.read_ids
+}
+
+/// Read_ids labelled `label` in a `get --controls` table, or every read of another read
+/// list. `both_groups` is set when the file holds the selected and the control reads,
+/// which only a labelled table can.
+fn load_read_group(fpath: &Path, label: &str, both_groups: bool, opts: &HashMap<String, String>) -> Vec<String> {
+    match load_labelled_read_ids(fpath, label) {
+        Some(read_ids) => read_ids,
+        None if both_groups => {
+            eprintln!("{} has no label column, pass the selected and control reads as two files", fpath.display());
+            exit(1);
+        }
+        None => load_read_list(fpath, parse_list_format_opt(opts), &parse_id_rules_opt(opts)),
+    }
 }
 
 fn filter_main(args: Vec<String>) {
-    if args.len() != 4 {
-        println!("usage: bad_reads filter <read_ids path> <slow5_file path> <out_file path> <filter_mode>");
+    let (args, opts) = split_opts(
+        args,
+        &["--mux", "--start", "--len", "--duration", "--end-reason", "--region", "--layout", "--complement", "--missing-out", "--id-rules", "--list-format"],
+        &["--any", "--strict"],
+    );
+    
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("usage: bad_reads filter [read_ids path] <slow5_file path> <out_file path> <filter_mode> [options]");
+        eprintln!("read_ids can be a plain list, fastq(.gz), sam/bam, paf or sequencing_summary file");
+        eprintln!("without a read_ids file every read in the slow5 file is considered");
+        eprintln!("options:");
+        eprintln!("  --mux <mux,..>          keep reads with one of the given start_mux values");
+        eprintln!("  --start <min:max>       keep reads starting within the window (secs)");
+        eprintln!("  --len <min:max>         keep reads with len_raw_signal within the range (samples)");
+        eprintln!("  --duration <min:max>    keep reads with duration within the range (secs)");
+        eprintln!("  --end-reason <reason,..> keep reads with one of the given end_reasons");
+        eprintln!("  --region <rows>,<cols>  keep reads from channels within the rows and cols, e.g. <0:8,0:16>");
+        eprintln!("  --layout <layout>       flowcell for --region: minion | flongle | promethion (default: from slow5 header)");
+        eprintln!("  --any                   keep reads matching any option instead of all, still within the odd/even mode");
+        eprintln!("  --complement <path>     write the reads that were not kept into a second file");
+        eprintln!("  --missing-out <path>    write read_ids that are not in the slow5 file into a file");
+        eprintln!("  --strict                stop at the first read_id that is not in the slow5 file");
+        eprintln!("  --id-rules <rule,..>    read_id normalisation: trim,marker,field,prefix | all | none (default: all)");
+        eprintln!("  --list-format <format>  read_ids format: text | fastq | sam | bam | paf | summary (default: detected)");
         exit(1);
     }
     
-    let read_ids_fpath = Path::new(&args[0]);
-    let slow5_fpath = Path::new(&args[1]);
-    let out_fpath = Path::new(&args[2]);
-    let read_mode_arg = &args[3];
+    let (read_ids_fpath, args) = match args.len() {
+        4 => (Some(Path::new(&args[0])), &args[1..]),
+        _ => (None, &args[..]),
+    };
+    let slow5_fpath = Path::new(&args[0]);
+    let out_fpath = Path::new(&args[1]);
+    let complement_fpath = opts.get("--complement").map(Path::new);
+    let missing_fpath = opts.get("--missing-out").map(Path::new);
+    let strict = opts.contains_key("--strict");
+    let normaliser = parse_id_rules_opt(&opts);
+    let list_format = parse_list_format_opt(&opts);
+    let read_mode_arg = &args[2];
     
-    let filter_mode = match read_mode_arg.as_str() {
-        "odd" => FilterMode::Odd,
-        "even" => FilterMode::Even,
+    let mut filter = match read_mode_arg.as_str() {
+        "odd" => ReadFilter::from(FilterMode::Odd),
+        "even" => ReadFilter::from(FilterMode::Even),
+        "all" => ReadFilter::default(),
         _ => {
-            println!("valid modes: <odd> | <even>");
+            eprintln!("valid modes: <odd> | <even> | <all>");
             exit(1);
         }
     };
     
-    if !read_ids_fpath.exists() {
-        println!("invalid read_list path");
+    if let Some(muxs) = opts.get("--mux") {
+        let muxs = muxs.split(',').map(|mux| mux.parse::<u8>()).collect::<Result<Vec<u8>, _>>();
+        match muxs {
+            Ok(muxs) => filter.conds.push(ReadCond::Mux(muxs)),
+            Err(_) => {
+                eprintln!("invalid mux list, expected e.g. <1,2>");
+                exit(1);
+            }
+        }
+    }
+    if let Some(range) = parse_range_opt(&opts, "--start") {
+        filter.conds.push(ReadCond::StartTime(range));
+    }
+    if let Some(range) = parse_range_opt(&opts, "--len") {
+        filter.conds.push(ReadCond::SignalLen(range));
+    }
+    if let Some(range) = parse_range_opt(&opts, "--duration") {
+        filter.conds.push(ReadCond::Duration(range));
+    }
+    if let Some(reasons) = opts.get("--end-reason") {
+        filter.conds.push(ReadCond::EndReason(reasons.split(',').map(String::from).collect()));
+    }
+    if opts.contains_key("--any") {
+        filter.logic = FilterLogic::Or;
+    }
+    
+    if read_ids_fpath.is_some_and(|read_ids_fpath| !read_ids_fpath.exists()) {
+        eprintln!("invalid read_list path");
         exit(1);
     }
     
     if !slow5_fpath.exists() {
-        println!("invalid slow5 path");
+        eprintln!("invalid slow5 path");
         exit(1);
     }
     
-    let out_file = OpenOptions::new()
+    if let Some(region) = opts.get("--region") {
+        let ranges = region.split_once(',').and_then(|(rows, cols)| Some((ValueRange::parse(rows)?, ValueRange::parse(cols)?)));
+        let Some((rows, cols)) = ranges else {
+            eprintln!("invalid region, expected <rows>,<cols> with each <min:max>, <min:> or <:max>");
+            exit(1);
+        };
+        filter.conds.push(ReadCond::Region(parse_layout_opt(&opts, Some(slow5_fpath)), rows, cols));
+    }
+    
+    let out_file = create_out_file(out_fpath);
+    let complement_file = complement_fpath.map(create_out_file);
+    let missing_file = missing_fpath.map(create_out_file);
+    
+    let read_ids = read_ids_fpath.map(|read_ids_fpath| load_read_list(read_ids_fpath, list_format, &normaliser));
+    
+    log_info!("filtering reads...");
+    let filtered_reads = partition_reads(read_ids.as_deref(), slow5_fpath, &filter, strict);
+    
+    if !filtered_reads.missing.is_empty() {
+        log_warn!("{} read_ids were not found in the slow5 file", filtered_reads.missing.len());
+    }
+    
+    log_info!("writing read_ids into file...");
+    write_read_ids(out_file, &filtered_reads.passed);
+    
+    if let Some(complement_file) = complement_file {
+        write_read_ids(complement_file, &filtered_reads.failed);
+    }
+    
+    if let Some(missing_file) = missing_file {
+        write_read_ids(missing_file, &filtered_reads.missing);
+    }
+    
+    log_info!("all done!");
+}
+
+fn features_main(args: Vec<String>) {
+    let (args, opts) = split_opts(args, &["--edge", "--saturation", "--id-rules", "--list-format"], &[]);
+    
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("usage: bad_reads features [read_ids path] <slow5_file path> <out_file path> [options]");
+        eprintln!("writes per-read signal statistics in pA as tsv");
+        eprintln!("without a read_ids file every read in the slow5 file is used");
+        eprintln!("options:");
+        eprintln!("  --edge <secs>           length of the start and end windows (default: 1)");
+        eprintln!("  --saturation <min:max>  pA range outside which samples are saturated (default: ADC limits)");
+        eprintln!("  --id-rules <rule,..>    read_id normalisation: trim,marker,field,prefix | all | none (default: all)");
+        eprintln!("  --list-format <format>  read_ids format: text | fastq | sam | bam | paf | summary (default: detected)");
+        exit(1);
+    }
+    
+    let (read_ids_fpath, args) = match args.len() {
+        3 => (Some(Path::new(&args[0])), &args[1..]),
+        _ => (None, &args[..]),
+    };
+    let slow5_fpath = Path::new(&args[0]);
+    let out_fpath = Path::new(&args[1]);
+    let normaliser = parse_id_rules_opt(&opts);
+    let list_format = parse_list_format_opt(&opts);
+    
+    let mut feature_opts = FeatureOptions {
+        saturation: parse_range_opt(&opts, "--saturation"),
+        ..Default::default()
+    };
+    if let Some(edge) = opts.get("--edge") {
+        match edge.parse::<f64>() {
+            Ok(edge) if edge > 0.0 => feature_opts.edge_secs = edge,
+            _ => {
+                eprintln!("invalid edge window {}, expected secs", edge);
+                exit(1);
+            }
+        }
+    }
+    
+    if read_ids_fpath.is_some_and(|read_ids_fpath| !read_ids_fpath.exists()) {
+        eprintln!("invalid read_list path");
+        exit(1);
+    }
+    
+    if !slow5_fpath.exists() {
+        eprintln!("invalid slow5 path");
+        exit(1);
+    }
+    
+    let mut out_file = BufWriter::new(create_out_file(out_fpath));
+    let read_ids = read_ids_fpath.map(|read_ids_fpath| load_read_list(read_ids_fpath, list_format, &normaliser));
+    
+    log_info!("extracting signal features...");
+    writeln!(out_file, "{}", FEATURES_TSV_HEADER).expect("error writing to out file");
+    let missing = extract_features(slow5_fpath, read_ids.as_deref(), &feature_opts, |features| {
+        writeln!(out_file, "{}", features.tsv_row()).expect("error writing to out file");
+    });
+    out_file.flush().expect("error writing to out file");
+    
+    if !missing.is_empty() {
+        log_warn!("{} read_ids were not found in the slow5 file", missing.len());
+    }
+    
+    log_info!("all done!");
+}
+
+fn create_out_file(out_fpath: &Path) -> File {
+    OpenOptions::new()
         .create_new(true)
-        .write(true)
         .append(true)
         .open(out_fpath)
-        .expect("could not open out file");
-    
-    println!("filtering reads...");
-    let filtered_reads = filter_reads(read_ids_fpath, slow5_fpath, filter_mode);
-    
-    println!("writing read_ids into file...");
+        .expect("could not open out file")
+}
+
+fn write_read_ids<S: Display>(out_file: File, read_ids: &[S]) {
     let mut out_file = BufWriter::new(out_file);
     
-    for bad_read in filtered_reads.into_iter() {
-        writeln!(out_file, "{}", bad_read).expect("error writing read_id to out file");
+    for read_id in read_ids.iter() {
+        writeln!(out_file, "{}", read_id).expect("error writing read_id to out file");
     }
-    
-    println!("all done!");
 }
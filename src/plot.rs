@@ -0,0 +1,190 @@
+//! SVG heatmaps of the flowcell, one cell per channel in its physical position.
+
+use std::{collections::{HashMap, HashSet}, fmt::Write};
+
+use crate::{ChannelPos, CountComparison, FlowcellLayout, PoreState, ReadId, ReadMeta, ScanRound};
+
+const CELL: u32 = 16;
+const MARGIN: u32 = 10;
+const TITLE_HEIGHT: u32 = 24;
+const LEGEND_HEIGHT: u32 = 40;
+const LEGEND_WIDTH: u32 = 160;
+
+const ALIVE_FILL: &str = "#2c9f45";
+const DEAD_FILL: &str = "#c8322f";
+const EMPTY_FILL: &str = "#e6e6e6";
+const SCALE_LOW: [u8; 3] = [0xf7, 0xfb, 0xff];
+const SCALE_HIGH: [u8; 3] = [0x08, 0x30, 0x6b];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Colour of `frac` (0 to 1) on the count scale.
+fn scale_fill(frac: f64) -> String {
+    let frac = if frac.is_finite() { frac.clamp(0.0, 1.0) } else { 0.0 };
+    let channel = |i: usize| (SCALE_LOW[i] as f64 + (SCALE_HIGH[i] as f64 - SCALE_LOW[i] as f64) * frac).round() as u8;
+
+    format!("#{:02x}{:02x}{:02x}", channel(0), channel(1), channel(2))
+}
+
+/// Document with a title, the grid area starts at (MARGIN, MARGIN + TITLE_HEIGHT).
+fn open_svg(layout: FlowcellLayout, title: &str) -> String {
+    let (rows, cols) = layout.dims();
+    let width = (cols * CELL + 2 * MARGIN).max(LEGEND_WIDTH * 2 + 2 * MARGIN);
+    let height = rows * CELL + 2 * MARGIN + TITLE_HEIGHT + LEGEND_HEIGHT;
+
+    let mut ret = String::new();
+    writeln!(ret, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">", width, height, width, height).unwrap();
+    writeln!(ret, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>").unwrap();
+    writeln!(ret, "<text x=\"{}\" y=\"{}\" font-size=\"14\">{}</text>", MARGIN, MARGIN + 14, escape(title)).unwrap();
+
+    ret
+}
+
+fn cell_origin(pos: ChannelPos) -> (u32, u32) {
+    (MARGIN + pos.col * CELL, MARGIN + TITLE_HEIGHT + pos.row * CELL)
+}
+
+fn legend_y(layout: FlowcellLayout) -> u32 {
+    MARGIN + TITLE_HEIGHT + layout.dims().0 * CELL + 12
+}
+
+/// Heatmap of a count per channel, e.g. reads or selected reads. Channels without a
+/// count are drawn as zero.
+pub fn channel_counts_svg(layout: FlowcellLayout, title: &str, counts: &HashMap<u32, usize>) -> String {
+    let mut ret = open_svg(layout, title);
+    let max = counts.values().copied().max().unwrap_or(0);
+
+    for channel in 1..=layout.channels() {
+        let (x, y) = cell_origin(layout.position(channel).unwrap());
+        let count = counts.get(&channel).copied().unwrap_or(0);
+        let fill = scale_fill(count as f64 / max as f64);
+        writeln!(
+            ret,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"#cccccc\" stroke-width=\"0.5\"><title>channel {}: {}</title></rect>",
+            x, y, CELL, CELL, fill, channel, count,
+        ).unwrap();
+    }
+
+    let y = legend_y(layout);
+    writeln!(ret, "<defs><linearGradient id=\"scale\"><stop offset=\"0\" stop-color=\"{}\"/><stop offset=\"1\" stop-color=\"{}\"/></linearGradient></defs>", scale_fill(0.0), scale_fill(1.0)).unwrap();
+    writeln!(ret, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"10\" fill=\"url(#scale)\" stroke=\"#999\" stroke-width=\"0.5\"/>", MARGIN, y, LEGEND_WIDTH).unwrap();
+    writeln!(ret, "<text x=\"{}\" y=\"{}\">0</text>", MARGIN, y + 24).unwrap();
+    writeln!(ret, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>", MARGIN + LEGEND_WIDTH, y + 24, max).unwrap();
+    ret.push_str("</svg>\n");
+
+    ret
+}
+
+/// Pore states of one scan round, every channel split into a 2 x 2 grid of muxes
+/// 1 to 4. Pores without a scan in the round are grey.
+pub fn scan_round_svg(layout: FlowcellLayout, round: &ScanRound) -> String {
+    let title = format!("pore states, scan {} ({:.0} s)", round.round + 1, round.secs);
+    let mut ret = open_svg(layout, &title);
+    let states = round.pores.iter().copied().collect::<HashMap<(u32, u8), PoreState>>();
+    let half = CELL / 2;
+
+    for channel in 1..=layout.channels() {
+        let (x, y) = cell_origin(layout.position(channel).unwrap());
+        for mux in 1..=4u8 {
+            let (fill, state) = match states.get(&(channel, mux)) {
+                Some(PoreState::Alive) => (ALIVE_FILL, "alive"),
+                Some(PoreState::Dead) => (DEAD_FILL, "dead"),
+                None => (EMPTY_FILL, "not scanned"),
+            };
+            let (dx, dy) = (((mux - 1) % 2) as u32 * half, ((mux - 1) / 2) as u32 * half);
+            writeln!(
+                ret,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"white\" stroke-width=\"0.5\"><title>channel {} mux {}: {}</title></rect>",
+                x + dx, y + dy, half, half, fill, channel, mux, state,
+            ).unwrap();
+        }
+    }
+
+    let y = legend_y(layout);
+    for (i, (fill, label)) in [(ALIVE_FILL, "alive"), (DEAD_FILL, "dead"), (EMPTY_FILL, "not scanned")].iter().enumerate() {
+        let x = MARGIN + i as u32 * 90;
+        writeln!(ret, "<rect x=\"{}\" y=\"{}\" width=\"10\" height=\"10\" fill=\"{}\"/>", x, y, fill).unwrap();
+        writeln!(ret, "<text x=\"{}\" y=\"{}\">{}</text>", x + 14, y + 10, label).unwrap();
+    }
+    ret.push_str("</svg>\n");
+
+    ret
+}
+
+/// Reads per channel, only counting `selected` reads if given.
+pub fn reads_per_channel(read_metas: &[ReadMeta], selected: Option<&HashSet<ReadId>>) -> HashMap<u32, usize> {
+    let mut ret = HashMap::new();
+    for meta in read_metas.iter() {
+        if selected.is_none_or(|selected| selected.contains(&meta.read_id)) {
+            *ret.entry(meta.channel).or_insert(0) += 1;
+        }
+    }
+
+    ret
+}
+
+const SCATTER_SIZE: u32 = 480;
+const AXIS_MARGIN: u32 = 50;
+
+/// Log-log scatter of selected against all reads per name, both axes log2(count + 1).
+/// Names with a q-value below `q_threshold` are highlighted and the dashed line is
+/// where names with no enrichment lie.
+pub fn count_scatter_svg(title: &str, comparisons: &[CountComparison], q_threshold: f64) -> String {
+    let width = SCATTER_SIZE + AXIS_MARGIN + 2 * MARGIN;
+    let height = SCATTER_SIZE + AXIS_MARGIN + 2 * MARGIN + TITLE_HEIGHT;
+    let max_log = comparisons.iter()
+        .map(|comparison| comparison.all.max(comparison.selected))
+        .max()
+        .map_or(1.0, |max| ((max + 1) as f64).log2().ceil().max(1.0));
+
+    let (x0, y0) = (MARGIN + AXIS_MARGIN, MARGIN + TITLE_HEIGHT + SCATTER_SIZE);
+    let scale = SCATTER_SIZE as f64 / max_log;
+    let to_x = |count: f64| x0 as f64 + (count + 1.0).log2() * scale;
+    let to_y = |count: f64| y0 as f64 - (count + 1.0).log2() * scale;
+
+    let mut ret = String::new();
+    writeln!(ret, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">", width, height, width, height).unwrap();
+    writeln!(ret, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>").unwrap();
+    writeln!(ret, "<text x=\"{}\" y=\"{}\" font-size=\"14\">{}</text>", MARGIN, MARGIN + 14, escape(title)).unwrap();
+
+    // axes with a tick at every power of two
+    writeln!(ret, "<path d=\"M{} {}V{}H{}\" fill=\"none\" stroke=\"black\"/>", x0, y0 - SCATTER_SIZE, y0, x0 + SCATTER_SIZE).unwrap();
+    for power in 0..=max_log as u32 {
+        let offset = power as f64 * scale;
+        let label = (1u64 << power) - 1;
+        writeln!(ret, "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>", x0 as f64 + offset, y0 + 16, label).unwrap();
+        writeln!(ret, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>", x0 - 4, y0 as f64 - offset + 4.0, label).unwrap();
+    }
+    writeln!(ret, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">all reads</text>", x0 + SCATTER_SIZE / 2, y0 + 36).unwrap();
+    writeln!(
+        ret,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" transform=\"rotate(-90 {} {})\">selected reads</text>",
+        MARGIN + 10, y0 - SCATTER_SIZE / 2, MARGIN + 10, y0 - SCATTER_SIZE / 2,
+    ).unwrap();
+
+    let all_total = comparisons.iter().map(|comparison| comparison.all).sum::<u64>() as f64;
+    let selected_total = comparisons.iter().map(|comparison| comparison.selected).sum::<u64>() as f64;
+    if all_total > 0.0 {
+        let points = (0..=100)
+            .map(|i| {
+                let all = 2f64.powf(max_log * i as f64 / 100.0) - 1.0;
+                format!("{:.1},{:.1}", to_x(all), to_y(all * selected_total / all_total))
+            })
+            .collect::<Vec<String>>();
+        writeln!(ret, "<polyline points=\"{}\" fill=\"none\" stroke=\"#888\" stroke-dasharray=\"4 3\"/>", points.join(" ")).unwrap();
+    }
+
+    for comparison in comparisons.iter() {
+        let fill = if comparison.q_value < q_threshold { DEAD_FILL } else { "#3b6ea8" };
+        writeln!(
+            ret,
+            "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"{}\" fill-opacity=\"0.7\"><title>{}: {} / {}, q = {:.3e}</title></circle>",
+            to_x(comparison.all as f64), to_y(comparison.selected as f64), fill, escape(&comparison.name), comparison.selected, comparison.all, comparison.q_value,
+        ).unwrap();
+    }
+    ret.push_str("</svg>\n");
+
+    ret
+}
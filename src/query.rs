@@ -0,0 +1,214 @@
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}};
+
+use crate::{gen_pore_mux_map, gen_read_timestamps_with, load_read_meta, match_each, sample_controls, stream_read_timestamps, ControlOptions, ControlSet, Match, MatchParams, PoreMuxStats, PoreState, ReadId, ReadMode, ReadTimestamp, ScanOptions, ValueRange};
+
+/// Bad-read selection in one place: which scans to look at, how many reads to take
+/// around each and where to load the run from.
+#[derive(Clone)]
+pub struct BadReadQuery {
+    slow5_fpath: Option<PathBuf>,
+    scan_data_fpath: Option<PathBuf>,
+    scan_opts: ScanOptions,
+    pore_states: Vec<PoreState>,
+    read_mode: ReadMode,
+    reads_per_scan: usize,
+    scan_window: ValueRange,
+    max_gap: Option<f64>,
+    channels: Option<HashSet<u32>>,
+}
+
+/// Counts describing a query run.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QueryStats {
+    /// reads looked at
+    pub reads: usize,
+    /// reads on a pore without any scan entry
+    pub reads_without_scans: usize,
+    /// scans in the selected states, window and channels, by state
+    pub dead_scans: ScanCounts,
+    pub alive_scans: ScanCounts,
+    pub matches: usize,
+    pub pores: PoreCounts,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ScanCounts {
+    /// scans with at least one read
+    pub matched: usize,
+    pub unmatched: usize,
+}
+
+/// Pores by the inputs they appear in.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PoreCounts {
+    pub both: usize,
+    /// pores with scans but no reads
+    pub scans_only: usize,
+    /// pores with reads but no scans
+    pub reads_only: usize,
+}
+
+pub struct QueryResult {
+    /// every match with its read, ordered by channel, pore and scan start time
+    pub matches: Vec<(Match, ReadTimestamp)>,
+    pub stats: QueryStats,
+}
+
+impl Default for BadReadQuery {
+    fn default() -> Self {
+        BadReadQuery {
+            slow5_fpath: None,
+            scan_data_fpath: None,
+            scan_opts: ScanOptions::default(),
+            pore_states: vec![PoreState::Dead],
+            read_mode: ReadMode::Last,
+            reads_per_scan: 1,
+            scan_window: ValueRange::default(),
+            max_gap: None,
+            channels: None,
+        }
+    }
+}
+
+impl BadReadQuery {
+    /// Last read before every dead scan, on all channels.
+    pub fn new() -> Self {
+        BadReadQuery::default()
+    }
+
+    /// slow5 file and mux scan csv `run` loads the reads and scans from.
+    pub fn sources<P: AsRef<Path>, Q: AsRef<Path>>(mut self, slow5_fpath: P, scan_data_fpath: Q) -> Self {
+        self.slow5_fpath = Some(slow5_fpath.as_ref().into());
+        self.scan_data_fpath = Some(scan_data_fpath.as_ref().into());
+        self
+    }
+
+    /// How `run` scans the slow5 file. With a memory budget the timestamps are
+    /// sorted externally and streamed into the matcher.
+    pub fn scan_options(mut self, scan_opts: ScanOptions) -> Self {
+        self.scan_opts = scan_opts;
+        self
+    }
+
+    /// Scan states to match reads to.
+    pub fn pore_states(mut self, pore_states: &[PoreState]) -> Self {
+        self.pore_states = pore_states.to_vec();
+        self
+    }
+
+    pub fn mode(mut self, read_mode: ReadMode) -> Self {
+        self.read_mode = read_mode;
+        self
+    }
+
+    /// Number of reads taken before (or after) each scan, the closest ones first.
+    pub fn reads_per_scan(mut self, reads_per_scan: usize) -> Self {
+        self.reads_per_scan = reads_per_scan.max(1);
+        self
+    }
+
+    /// Only match scans starting within `scan_window` (secs).
+    pub fn scan_window(mut self, scan_window: ValueRange) -> Self {
+        self.scan_window = scan_window;
+        self
+    }
+
+    /// Skip reads starting more than `max_gap` secs away from their scan.
+    pub fn max_gap(mut self, max_gap: f64) -> Self {
+        self.max_gap = Some(max_gap);
+        self
+    }
+
+    pub fn channels<I: IntoIterator<Item = u32>>(mut self, channels: I) -> Self {
+        self.channels = Some(channels.into_iter().collect());
+        self
+    }
+
+    fn params(&self) -> MatchParams<'_> {
+        MatchParams {
+            pore_states: &self.pore_states,
+            read_mode: self.read_mode,
+            reads_per_scan: self.reads_per_scan,
+            scan_window: self.scan_window,
+            max_gap: self.max_gap,
+            channels: self.channels.as_ref(),
+        }
+    }
+
+    /// Loads the sources and runs the query.
+    pub fn run(&self) -> QueryResult {
+        let slow5_fpath = self.slow5_fpath.as_ref().expect("BadReadQuery::run needs sources");
+        let scan_data_fpath = self.scan_data_fpath.as_ref().expect("BadReadQuery::run needs sources");
+
+        let pore_mux_map = gen_pore_mux_map(scan_data_fpath);
+
+        if self.scan_opts.memory_budget.is_some() {
+            self.run_stream(&pore_mux_map, stream_read_timestamps(slow5_fpath, &self.scan_opts))
+        } else {
+            self.run_stream(&pore_mux_map, gen_read_timestamps_with(slow5_fpath, &self.scan_opts))
+        }
+    }
+
+    /// Runs the query and samples control reads for its matches. Controls are matched
+    /// on read length too, so the run is held in memory and the memory budget is not
+    /// supported.
+    pub fn run_with_controls(&self, control_opts: &ControlOptions) -> (QueryResult, ControlSet) {
+        let slow5_fpath = self.slow5_fpath.as_ref().expect("BadReadQuery::run needs sources");
+        let scan_data_fpath = self.scan_data_fpath.as_ref().expect("BadReadQuery::run needs sources");
+
+        let pore_mux_map = gen_pore_mux_map(scan_data_fpath);
+        let mut read_metas = load_read_meta(slow5_fpath, &self.scan_opts);
+        // in read timestamp order, so the timestamps can be made one at a time
+        read_metas.sort_by(|a, b| {
+            a.secs_start().total_cmp(&b.secs_start())
+                .then(a.channel.cmp(&b.channel))
+                .then(a.mux.cmp(&b.mux))
+                .then_with(|| a.read_id.cmp(&b.read_id))
+        });
+
+        let result = self.run_stream(&pore_mux_map, read_metas.iter().cloned().map(ReadTimestamp::from));
+        let selected = result.matches.iter().map(|(_, ts)| ts).collect::<Vec<&ReadTimestamp>>();
+        let controls = sample_controls(&pore_mux_map, &read_metas, &selected, control_opts);
+
+        (result, controls)
+    }
+
+    /// Runs the query on an already loaded run, which can be queried again with other settings.
+    pub fn run_on(&self, pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, read_timestamps: &[ReadTimestamp]) -> QueryResult {
+        let (matches, stats) = match_each(pore_mux_map, read_timestamps, &self.params());
+
+        QueryResult {
+            matches: matches.into_iter().map(|(m, ts)| (m, ts.clone())).collect(),
+            stats,
+        }
+    }
+
+    /// Runs the query on sorted reads, holding only the matched ones.
+    pub fn run_stream<I: IntoIterator<Item = ReadTimestamp>>(&self, pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, reads: I) -> QueryResult {
+        let (matches, stats) = match_each(pore_mux_map, reads, &self.params());
+
+        QueryResult { matches, stats }
+    }
+}
+
+impl QueryStats {
+    pub fn scans(&self, pore_state: PoreState) -> ScanCounts {
+        match pore_state {
+            PoreState::Dead => self.dead_scans,
+            PoreState::Alive => self.alive_scans,
+        }
+    }
+
+    pub(crate) fn scans_mut(&mut self, pore_state: PoreState) -> &mut ScanCounts {
+        match pore_state {
+            PoreState::Dead => &mut self.dead_scans,
+            PoreState::Alive => &mut self.alive_scans,
+        }
+    }
+}
+
+impl QueryResult {
+    pub fn read_ids(&self) -> Vec<&ReadId> {
+        self.matches.iter().map(|(_, ts)| &ts.read_id).collect()
+    }
+}
@@ -0,0 +1,354 @@
+use std::{cmp::Ordering, collections::HashSet, fmt, io::{self, BufRead}, path::Path};
+
+use crate::{open_reader, read_alignments, text_format, AlignFormat};
+
+/// Inputs a read list can be taken from. Any of them may be gzipped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadListFormat {
+    /// one read_id per line
+    Text,
+    /// single or multi-line records
+    Fastq,
+    Sam,
+    Bam,
+    Paf,
+    /// sequencing_summary.txt style table with a `read_id` column
+    SeqSummary,
+}
+
+/// Rules for turning a line of a read list into a bare read_id. Read lists from
+/// basecallers, fast5 conversions and fastq exports decorate ids in different ways.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReadIdNormaliser {
+    /// trim surrounding whitespace and `\r` line endings
+    pub trim: bool,
+    /// drop a leading fastq `@` or fasta `>`
+    pub strip_marker: bool,
+    /// keep only the first whitespace separated field, e.g. of a fastq header
+    pub first_field: bool,
+    /// drop the `read_` prefix used by fast5 read groups
+    pub strip_read_prefix: bool,
+}
+
+/// A read_id as kept for every read of a run. Canonical (lowercase, hyphenated)
+/// UUIDs are stored as their 16 bytes, anything else as a boxed string.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ReadId {
+    Uuid([u8; 16]),
+    Other(Box<str>),
+}
+
+#[derive(Default)]
+pub struct ReadIdList {
+    pub read_ids: Vec<String>,
+    /// number of lines that had to be rewritten to get their read_id
+    pub rewritten: usize,
+}
+
+impl ReadListFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(ReadListFormat::Text),
+            "fastq" => Some(ReadListFormat::Fastq),
+            "sam" => Some(ReadListFormat::Sam),
+            "bam" => Some(ReadListFormat::Bam),
+            "paf" => Some(ReadListFormat::Paf),
+            "summary" => Some(ReadListFormat::SeqSummary),
+            _ => None,
+        }
+    }
+
+    /// Sniffs the format from the first lines of the (decompressed) file.
+    pub fn detect(fpath: &Path) -> ReadListFormat {
+        if AlignFormat::detect(fpath) == Some(AlignFormat::Bam) {
+            return ReadListFormat::Bam;
+        }
+
+        let lines = open_reader(fpath)
+            .lines()
+            .take(8)
+            .collect::<Result<Vec<String>, _>>()
+            .expect("could not read read_ids file");
+        let Some(first) = lines.first() else {
+            return ReadListFormat::Text;
+        };
+
+        if ["@HD\t", "@SQ\t", "@RG\t", "@PG\t", "@CO\t"].iter().any(|tag| first.starts_with(tag)) {
+            ReadListFormat::Sam
+        } else if first.starts_with('@') && lines[1..].iter().any(|line| line.starts_with('+')) {
+            ReadListFormat::Fastq
+        } else if first.split('\t').any(|col| col == "read_id") {
+            ReadListFormat::SeqSummary
+        } else {
+            match text_format(first) {
+                Some(AlignFormat::Paf) => ReadListFormat::Paf,
+                Some(AlignFormat::Sam) => ReadListFormat::Sam,
+                _ => ReadListFormat::Text,
+            }
+        }
+    }
+}
+
+const UUID_HYPHENS: [usize; 4] = [8, 13, 18, 23];
+
+fn hex_val(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+impl ReadId {
+    /// Parses `8-4-4-4-12` lowercase hex, the form that formats back unchanged.
+    fn parse_uuid(read_id: &str) -> Option<[u8; 16]> {
+        let read_id = read_id.as_bytes();
+        if read_id.len() != 36 || UUID_HYPHENS.iter().any(|i| read_id[*i] != b'-') {
+            return None;
+        }
+
+        let mut ret = [0u8; 16];
+        let mut digits = read_id.iter().filter(|c| **c != b'-');
+        for byte in ret.iter_mut() {
+            *byte = hex_val(*digits.next()?)? << 4 | hex_val(*digits.next()?)?;
+        }
+
+        Some(ret)
+    }
+}
+
+impl From<&str> for ReadId {
+    fn from(read_id: &str) -> Self {
+        match ReadId::parse_uuid(read_id) {
+            Some(uuid) => ReadId::Uuid(uuid),
+            None => ReadId::Other(read_id.into()),
+        }
+    }
+}
+
+impl From<String> for ReadId {
+    fn from(read_id: String) -> Self {
+        match ReadId::parse_uuid(&read_id) {
+            Some(uuid) => ReadId::Uuid(uuid),
+            None => ReadId::Other(read_id.into_boxed_str()),
+        }
+    }
+}
+
+impl fmt::Display for ReadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadId::Uuid(uuid) => {
+                for (i, byte) in uuid.iter().enumerate() {
+                    if matches!(i, 4 | 6 | 8 | 10) {
+                        f.write_str("-")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            ReadId::Other(read_id) => f.write_str(read_id),
+        }
+    }
+}
+
+impl PartialEq<str> for ReadId {
+    fn eq(&self, other: &str) -> bool {
+        match self {
+            ReadId::Uuid(uuid) => ReadId::parse_uuid(other).is_some_and(|other| *uuid == other),
+            ReadId::Other(read_id) => **read_id == *other,
+        }
+    }
+}
+
+impl PartialEq<&str> for ReadId {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+/// Same order as comparing the read_ids as strings.
+impl Ord for ReadId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            // lowercase hex digits sort like the bytes they encode
+            (ReadId::Uuid(a), ReadId::Uuid(b)) => a.cmp(b),
+            (ReadId::Other(a), ReadId::Other(b)) => a.cmp(b),
+            _ => self.to_string().cmp(&other.to_string()),
+        }
+    }
+}
+
+impl PartialOrd for ReadId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl ReadIdList {
+    fn push(&mut self, field: &str, normaliser: &ReadIdNormaliser) {
+        let read_id = normaliser.normalise(field);
+        if read_id.is_empty() { return; }
+
+        if read_id != field {
+            self.rewritten += 1;
+        }
+        self.read_ids.push(read_id.into());
+    }
+
+    /// Keeps the first occurrence of every read_id, e.g. of reads with several alignments.
+    fn dedup(&mut self) {
+        let mut seen = HashSet::new();
+        self.read_ids.retain(|read_id| seen.insert(read_id.clone()));
+    }
+}
+
+impl Default for ReadIdNormaliser {
+    fn default() -> Self {
+        ReadIdNormaliser {
+            trim: true,
+            strip_marker: true,
+            first_field: true,
+            strip_read_prefix: true,
+        }
+    }
+}
+
+impl ReadIdNormaliser {
+    pub fn none() -> Self {
+        ReadIdNormaliser {
+            trim: false,
+            strip_marker: false,
+            first_field: false,
+            strip_read_prefix: false,
+        }
+    }
+
+    /// Parses a comma separated list of rules (`trim`, `marker`, `field`, `prefix`),
+    /// `all` or `none`.
+    pub fn from_rules(rules: &str) -> Option<Self> {
+        let mut ret = ReadIdNormaliser::none();
+
+        for rule in rules.split(',') {
+            match rule {
+                "all" => ret = ReadIdNormaliser::default(),
+                "none" => {},
+                "trim" => ret.trim = true,
+                "marker" => ret.strip_marker = true,
+                "field" => ret.first_field = true,
+                "prefix" => ret.strip_read_prefix = true,
+                _ => return None,
+            }
+        }
+
+        Some(ret)
+    }
+
+    pub fn normalise<'a>(&self, line: &'a str) -> &'a str {
+        let mut read_id = line;
+
+        if self.trim {
+            read_id = read_id.trim();
+        }
+        if self.strip_marker {
+            read_id = read_id.strip_prefix(['@', '>']).unwrap_or(read_id);
+        }
+        if self.first_field {
+            read_id = read_id.split_whitespace().next().unwrap_or("");
+        }
+        if self.strip_read_prefix {
+            read_id = read_id.strip_prefix("read_").unwrap_or(read_id);
+        }
+
+        read_id
+    }
+}
+
+/// Loads the read_ids of a read list in any of the `ReadListFormat`s, detected from its content.
+pub fn load_read_ids(read_ids_fpath: &Path, normaliser: &ReadIdNormaliser) -> io::Result<ReadIdList> {
+    load_read_ids_as(read_ids_fpath, ReadListFormat::detect(read_ids_fpath), normaliser)
+}
+
+/// Malformed fastq records and sequencing summaries without a `read_id` column are an
+/// `InvalidData` error.
+pub fn load_read_ids_as(read_ids_fpath: &Path, format: ReadListFormat, normaliser: &ReadIdNormaliser) -> io::Result<ReadIdList> {
+    let mut ret = ReadIdList::default();
+
+    match format {
+        ReadListFormat::Text => {
+            for line in open_reader(read_ids_fpath).lines() {
+                ret.push(&line?, normaliser);
+            }
+        }
+        ReadListFormat::Fastq => read_fastq_ids(read_ids_fpath, normaliser, &mut ret)?,
+        ReadListFormat::Sam | ReadListFormat::Bam | ReadListFormat::Paf => {
+            read_alignments(read_ids_fpath, |alignment| ret.push(&alignment.read_id, normaliser));
+        }
+        ReadListFormat::SeqSummary => {
+            let mut lines = open_reader(read_ids_fpath).lines();
+            let header = lines.next().ok_or_else(|| invalid_summary("empty sequencing summary"))??;
+            let read_id_col = header.split('\t').position(|col| col == "read_id").ok_or_else(|| invalid_summary("sequencing summary has no read_id column"))?;
+
+            for line in lines {
+                let line = line?;
+                if let Some(read_id) = line.split('\t').nth(read_id_col) {
+                    ret.push(read_id, normaliser);
+                }
+            }
+        }
+    }
+
+    if format != ReadListFormat::Text {
+        ret.dedup();
+    }
+
+    Ok(ret)
+}
+
+fn invalid_summary(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn invalid_fastq(line_no: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid fastq at line {}: {}", line_no, msg))
+}
+
+/// Takes the ids from fastq headers. Sequences and qualities may span several lines,
+/// the quality ends once it is as long as the sequence. Blank lines between records
+/// are skipped.
+fn read_fastq_ids(fastq_fpath: &Path, normaliser: &ReadIdNormaliser, ret: &mut ReadIdList) -> io::Result<()> {
+    let mut lines = open_reader(fastq_fpath).lines().enumerate().map(|(i, line)| line.map(|line| (i + 1, line)));
+
+    while let Some(line) = lines.next() {
+        let (line_no, header) = line?;
+        if header.trim().is_empty() {
+            continue;
+        }
+        let Some(header) = header.strip_prefix('@') else {
+            return Err(invalid_fastq(line_no, "expected an `@` header"));
+        };
+        ret.push(header.split_whitespace().next().unwrap_or(""), normaliser);
+
+        let mut seq_len = 0;
+        loop {
+            let Some(line) = lines.next() else {
+                return Err(invalid_fastq(line_no, "record has no `+` line"));
+            };
+            let (_, line) = line?;
+            if line.starts_with('+') {
+                break;
+            }
+            seq_len += line.trim_end().len();
+        }
+
+        let mut qual_len = 0;
+        while qual_len < seq_len {
+            let Some(line) = lines.next() else {
+                return Err(invalid_fastq(line_no, "record quality is shorter than its sequence"));
+            };
+            qual_len += line?.1.trim_end().len();
+        }
+    }
+
+    Ok(())
+}
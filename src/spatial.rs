@@ -0,0 +1,176 @@
+//! Spatial clustering of pore deaths on the flowcell, to tell bubbles or blocked
+//! regions from pores dying independently. Clustering is measured with Moran's I over
+//! channels sharing an edge, tested against random permutations of the channel values.
+
+use std::collections::HashMap;
+
+use crate::{pore_lifetimes, ChannelPos, FlowcellLayout, PoreMuxStats, PoreState, SplitMix64};
+
+/// Pore deaths on one channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelDeaths {
+    pub channel: u32,
+    pub pos: ChannelPos,
+    /// pores (muxes) with scans
+    pub pores: usize,
+    pub died: usize,
+    pub never_alive: usize,
+}
+
+/// Dead fraction of every channel for one round of scans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanRound {
+    /// zero-based, the n-th scan of every pore
+    pub round: usize,
+    /// earliest scan start in the round
+    pub secs: f64,
+    /// (channel, fraction of its scanned pores found dead)
+    pub values: Vec<(u32, f64)>,
+    /// state of every scanned pore, by channel then mux
+    pub pores: Vec<((u32, u8), PoreState)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoranTest {
+    /// channels with a value
+    pub channels: usize,
+    /// NaN if every channel has the same value
+    pub morans_i: f64,
+    /// expected I without clustering, `-1 / (channels - 1)`
+    pub expected: f64,
+    /// one-sided permutation p-value for clustering
+    pub p_value: f64,
+}
+
+impl ChannelDeaths {
+    /// Fraction of the pores ever alive that died, NaN without any.
+    pub fn dead_frac(&self) -> f64 {
+        self.died as f64 / (self.pores - self.never_alive) as f64
+    }
+}
+
+/// Deaths per channel of the layout, in channel order. Channels off the layout are
+/// left out.
+pub fn channel_deaths(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>, layout: FlowcellLayout) -> Vec<ChannelDeaths> {
+    let mut ret = Vec::<ChannelDeaths>::new();
+
+    for lifetime in pore_lifetimes(pore_mux_map) {
+        let Some(pos) = layout.position(lifetime.channel) else { continue; };
+        if ret.last().is_none_or(|last| last.channel != lifetime.channel) {
+            ret.push(ChannelDeaths { channel: lifetime.channel, pos, pores: 0, died: 0, never_alive: 0 });
+        }
+
+        let channel = ret.last_mut().unwrap();
+        channel.pores += 1;
+        channel.died += lifetime.death.is_some() as usize;
+        channel.never_alive += lifetime.last_alive.is_none() as usize;
+    }
+
+    ret
+}
+
+/// Groups the scans of every pore into rounds by their order in time, as mux scans
+/// run over all pores together.
+pub fn scan_rounds(pore_mux_map: &HashMap<(u32, u8), PoreMuxStats>) -> Vec<ScanRound> {
+    // per round, its earliest scan and the pores scanned
+    let mut rounds = Vec::<(f64, Vec<((u32, u8), PoreState)>)>::new();
+
+    for (key, pore_muxs) in pore_mux_map.iter() {
+        let mut muxs = pore_muxs.muxs.clone();
+        muxs.sort_by(|a, b| a.secs_start.total_cmp(&b.secs_start));
+
+        for (round, muxstat) in muxs.iter().enumerate() {
+            if rounds.len() <= round {
+                rounds.push((f64::INFINITY, Vec::new()));
+            }
+            let (secs, pores) = &mut rounds[round];
+            *secs = secs.min(muxstat.secs_start);
+            pores.push((*key, muxstat.pore_state));
+        }
+    }
+
+    rounds.into_iter()
+        .enumerate()
+        .map(|(round, (secs, mut pores))| {
+            pores.sort_by_key(|(key, _)| *key);
+            let values = pores.chunk_by(|a, b| a.0.0 == b.0.0)
+                .map(|channel| {
+                    let dead = channel.iter().filter(|(_, pore_state)| *pore_state == PoreState::Dead).count();
+                    (channel[0].0.0, dead as f64 / channel.len() as f64)
+                })
+                .collect();
+
+            ScanRound { round, secs, values, pores }
+        })
+        .collect()
+}
+
+/// Values and edges between neighbouring channels, by index into `values`.
+struct Neighbourhood {
+    values: Vec<f64>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Neighbourhood {
+    fn new(layout: FlowcellLayout, values: &[(u32, f64)]) -> Self {
+        let values = values.iter().filter(|(_, val)| val.is_finite()).copied().collect::<Vec<(u32, f64)>>();
+        let idx = values.iter().enumerate().map(|(i, (channel, _))| (*channel, i)).collect::<HashMap<u32, usize>>();
+
+        let mut edges = Vec::new();
+        for (i, (channel, _)) in values.iter().enumerate() {
+            for neighbour in layout.neighbours(*channel) {
+                if let Some(j) = idx.get(&neighbour) {
+                    edges.push((i, *j));
+                }
+            }
+        }
+
+        Neighbourhood { values: values.into_iter().map(|(_, val)| val).collect(), edges }
+    }
+
+    /// Sum of the cross products of neighbouring deviations from the mean.
+    fn cross(&self, values: &[f64], mean: f64) -> f64 {
+        self.edges.iter().map(|(i, j)| (values[*i] - mean) * (values[*j] - mean)).sum()
+    }
+}
+
+/// Moran's I of per-channel values over edge-sharing neighbours. Non-finite values and
+/// channels off the layout are left out; `permutations` random relabellings give the
+/// p-value.
+pub fn moran_test(layout: FlowcellLayout, values: &[(u32, f64)], permutations: usize, seed: u64) -> MoranTest {
+    let neighbourhood = Neighbourhood::new(layout, values);
+    let n = neighbourhood.values.len();
+    let mean = neighbourhood.values.iter().sum::<f64>() / n as f64;
+    let sum_sq = neighbourhood.values.iter().map(|val| (val - mean).powi(2)).sum::<f64>();
+    let scale = n as f64 / neighbourhood.edges.len() as f64 / sum_sq;
+
+    let morans_i = if sum_sq > 0.0 && !neighbourhood.edges.is_empty() {
+        scale * neighbourhood.cross(&neighbourhood.values, mean)
+    } else {
+        f64::NAN
+    };
+
+    let mut p_value = f64::NAN;
+    if morans_i.is_finite() && permutations > 0 {
+        let mut rng = SplitMix64::new(seed);
+        let mut shuffled = neighbourhood.values.clone();
+        let mut as_clustered = 0;
+        for _ in 0..permutations {
+            for i in (1..n).rev() {
+                let j = rng.below(i + 1);
+                shuffled.swap(i, j);
+            }
+            if scale * neighbourhood.cross(&shuffled, mean) >= morans_i {
+                as_clustered += 1;
+            }
+        }
+        p_value = (as_clustered + 1) as f64 / (permutations + 1) as f64;
+    }
+
+    MoranTest {
+        channels: n,
+        morans_i,
+        expected: if n > 1 { -1.0 / (n as f64 - 1.0) } else { f64::NAN },
+        p_value,
+    }
+}
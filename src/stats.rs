@@ -0,0 +1,192 @@
+//! Statistical tests used by the reports, written out here to keep the dependency
+//! list short.
+
+/// Lanczos approximation (g = 7), accurate to about 15 digits for `x > 0`.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFS[1..].iter().enumerate().fold(COEFS[0], |sum, (i, coef)| sum + coef / (x + i as f64 + 1.0));
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+fn ln_choose(n: u64, k: u64) -> f64 {
+    ln_gamma(n as f64 + 1.0) - ln_gamma(k as f64 + 1.0) - ln_gamma((n - k) as f64 + 1.0)
+}
+
+/// Two-sided Fisher's exact test of the table `[[a, b], [c, d]]`: the probability of
+/// the tables with the same margins that are no likelier than the observed one.
+pub fn fisher_exact(a: u64, b: u64, c: u64, d: u64) -> f64 {
+    let (row, col, n) = (a + b, a + c, a + b + c + d);
+    let (lo, hi) = (col.saturating_sub(n - row), row.min(col));
+    let ln_p = |x: u64| ln_choose(row, x) + ln_choose(n - row, col - x) - ln_choose(n, col);
+
+    // same relative tolerance as R's fisher.test
+    let observed = ln_p(a) + 1e-7_f64.ln_1p();
+    let mode = (((row + 1) as f64 * (col + 1) as f64 / (n + 2) as f64) as u64).clamp(lo, hi);
+
+    // each tail falls away from the mode, so only its part beyond the observed
+    // probability counts and terms too small to matter end it
+    let mut ret = 0.0;
+    let mut tail = |xs: &mut dyn Iterator<Item = u64>| {
+        for x in xs {
+            let ln_px = ln_p(x);
+            if ln_px > observed {
+                continue;
+            }
+            let px = ln_px.exp();
+            if px < ret * 1e-17 {
+                break;
+            }
+            ret += px;
+        }
+    };
+    tail(&mut (mode..=hi));
+    tail(&mut (lo..mode).rev());
+
+    ret.min(1.0)
+}
+
+/// Benjamini-Hochberg adjusted p-values, in the order of `p_values`.
+pub fn bh_adjust(p_values: &[f64]) -> Vec<f64> {
+    let m = p_values.len();
+    let mut order = (0..m).collect::<Vec<usize>>();
+    order.sort_by(|a, b| p_values[*a].total_cmp(&p_values[*b]));
+
+    let mut ret = vec![0.0; m];
+    let mut min = 1.0f64;
+    for (rank, i) in order.iter().enumerate().rev() {
+        min = min.min(p_values[*i] * m as f64 / (rank + 1) as f64);
+        ret[*i] = min;
+    }
+
+    ret
+}
+
+/// Complementary error function, with a fractional error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = [-1.26551223, 1.00002368, 0.37409196, 0.09678418, -0.18628806, 0.27886807, -1.13520398, 1.48851587, -0.82215223, 0.17087277]
+        .iter()
+        .rev()
+        .fold(0.0, |acc, coef| coef + t * acc);
+    let ret = t * (-z * z + poly).exp();
+
+    if x >= 0.0 { ret } else { 2.0 - ret }
+}
+
+/// Distribution of one metric in a group of reads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetricSummary {
+    pub n: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// lower and upper quartiles
+    pub q25: f64,
+    pub q75: f64,
+}
+
+/// One metric of the selected reads against the controls.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetricComparison {
+    pub metric: &'static str,
+    pub selected: MetricSummary,
+    pub control: MetricSummary,
+    /// Fisher's exact test for rates, Mann-Whitney U for the rest
+    pub p_value: f64,
+}
+
+/// Linearly interpolated quantile of sorted values, as R's default type 7.
+fn quantile(sorted: &[f64], prob: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let pos = prob * (sorted.len() - 1) as f64;
+    let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+impl MetricSummary {
+    /// NaN statistics without values.
+    pub fn of(values: &[f64]) -> MetricSummary {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        MetricSummary {
+            n: sorted.len(),
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            median: quantile(&sorted, 0.5),
+            q25: quantile(&sorted, 0.25),
+            q75: quantile(&sorted, 0.75),
+        }
+    }
+
+    /// Share of 1s among 0/1 values, the quantiles of which say nothing.
+    pub fn rate(values: &[f64]) -> MetricSummary {
+        MetricSummary { median: f64::NAN, q25: f64::NAN, q75: f64::NAN, ..MetricSummary::of(values) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MannWhitney {
+    /// U of the first sample
+    pub u: f64,
+    /// two-sided, from the normal approximation with tie and continuity corrections
+    pub p_value: f64,
+}
+
+/// Mann-Whitney U test of two samples. Non-finite values are left out; the p-value
+/// is NaN if either sample is then empty.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> MannWhitney {
+    let mut values = a.iter().map(|val| (*val, true))
+        .chain(b.iter().map(|val| (*val, false)))
+        .filter(|(val, _)| val.is_finite())
+        .collect::<Vec<(f64, bool)>>();
+    values.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let n_a = values.iter().filter(|(_, in_a)| *in_a).count() as f64;
+    let n_b = values.len() as f64 - n_a;
+    let n = values.len() as f64;
+    if n_a == 0.0 || n_b == 0.0 {
+        return MannWhitney { u: f64::NAN, p_value: f64::NAN };
+    }
+
+    // tied values share their average rank
+    let mut rank_sum = 0.0;
+    let mut ties = 0.0;
+    let mut start = 0;
+    for group in values.chunk_by(|x, y| x.0 == y.0) {
+        let rank = start as f64 + (group.len() as f64 + 1.0) / 2.0;
+        rank_sum += rank * group.iter().filter(|(_, in_a)| *in_a).count() as f64;
+        ties += (group.len() as f64).powi(3) - group.len() as f64;
+        start += group.len();
+    }
+
+    let u = rank_sum - n_a * (n_a + 1.0) / 2.0;
+    let sd = (n_a * n_b / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)))).sqrt();
+    let p_value = if sd > 0.0 {
+        let z = ((u - n_a * n_b / 2.0).abs() - 0.5).max(0.0) / sd;
+        erfc(z / std::f64::consts::SQRT_2).min(1.0)
+    } else {
+        1.0
+    };
+
+    MannWhitney { u, p_value }
+}
@@ -6,7 +6,7 @@ use super::*;
 fn filter_reads_odd() {
     let read_ids_fpath = Path::new("test_data/rand_readids_5.txt");
     let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
-    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, FilterMode::Odd);
+    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, &FilterMode::Odd.into());
     
     assert!(read_ids.len() == 2);
     assert!(read_ids[0] == "8bfec45c-b89e-4510-9469-e94bb415b8e4");
@@ -17,7 +17,7 @@ fn filter_reads_odd() {
 fn filter_reads_even() {
     let read_ids_fpath = Path::new("test_data/rand_readids_5.txt");
     let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
-    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, FilterMode::Even);
+    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, &FilterMode::Even.into());
     
     assert!(read_ids.len() == 3);
     assert!(read_ids[0] == "d62da1d5-971e-4e5d-9465-5715300e8523");
@@ -25,6 +25,250 @@ fn filter_reads_even() {
     assert!(read_ids[2] == "76b715cd-aaea-4ae1-8026-41c1772597ed");
 }
 
+#[test]
+fn filter_reads_mux_and_start() {
+    let read_ids_fpath = Path::new("test_data/rand_readids_5.txt");
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let filter = ReadFilter {
+        conds: vec![
+            ReadCond::Mux(vec![2, 4]),
+            ReadCond::StartTime(ValueRange { min: None, max: Some(30000.0) }),
+        ],
+        logic: FilterLogic::And,
+    };
+    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, &filter);
+    
+    assert!(read_ids.len() == 3);
+    assert!(read_ids[0] == "d62da1d5-971e-4e5d-9465-5715300e8523");
+    assert!(read_ids[1] == "8bfec45c-b89e-4510-9469-e94bb415b8e4");
+    assert!(read_ids[2] == "d56f390f-2e33-436e-9220-a93aca7dd11b");
+}
+
+#[test]
+fn filter_reads_end_reason_or_duration() {
+    let read_ids_fpath = Path::new("test_data/rand_readids_5.txt");
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let filter = ReadFilter {
+        conds: vec![
+            ReadCond::EndReason(vec!["unblock_mux_change".into()]),
+            ReadCond::Duration(ValueRange { min: None, max: Some(3.0) }),
+        ],
+        logic: FilterLogic::Or,
+    };
+    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, &filter);
+    
+    assert!(read_ids.len() == 2);
+    assert!(read_ids[0] == "d56f390f-2e33-436e-9220-a93aca7dd11b");
+    assert!(read_ids[1] == "503f0bd8-3a00-4c76-9f2e-c70ada3d418b");
+}
+
+#[test]
+fn filter_reads_mode_and_any_muxes() {
+    let read_ids_fpath = Path::new("test_data/rand_readids_5.txt");
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let mut filter = ReadFilter::from(FilterMode::Odd);
+    filter.conds.push(ReadCond::Mux(vec![4]));
+    filter.conds.push(ReadCond::Mux(vec![1]));
+    filter.logic = FilterLogic::Or;
+    
+    // the even channel reads on mux 4 and 1 are not kept
+    let read_ids = filter_reads(read_ids_fpath, slow5_fpath, &filter);
+    assert!(read_ids == ["8bfec45c-b89e-4510-9469-e94bb415b8e4"]);
+}
+
+#[test]
+fn partition_all_reads() {
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let filtered_reads = partition_reads(None, slow5_fpath, &FilterMode::Odd.into(), false);
+    
+    assert!(filtered_reads.passed.len() == 2);
+    assert!(filtered_reads.passed.contains(&"8bfec45c-b89e-4510-9469-e94bb415b8e4".to_string()));
+    assert!(filtered_reads.passed.contains(&"503f0bd8-3a00-4c76-9f2e-c70ada3d418b".to_string()));
+    
+    assert!(filtered_reads.failed.len() == 3);
+    assert!(filtered_reads.failed.contains(&"d62da1d5-971e-4e5d-9465-5715300e8523".to_string()));
+    assert!(filtered_reads.failed.contains(&"d56f390f-2e33-436e-9220-a93aca7dd11b".to_string()));
+    assert!(filtered_reads.failed.contains(&"76b715cd-aaea-4ae1-8026-41c1772597ed".to_string()));
+}
+
+#[test]
+fn partition_reads_missing_ids() {
+    let read_ids = load_read_ids(Path::new("test_data/rand_readids_5_missing.txt"), &ReadIdNormaliser::default()).unwrap().read_ids;
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let filtered_reads = partition_reads(Some(&read_ids), slow5_fpath, &FilterMode::Odd.into(), false);
+    
+    assert!(filtered_reads.passed.len() == 2);
+    assert!(filtered_reads.failed.len() == 3);
+    assert!(filtered_reads.missing.len() == 1);
+    assert!(filtered_reads.missing[0] == "00000000-0000-0000-0000-000000000000");
+}
+
+#[test]
+#[should_panic]
+fn partition_reads_missing_ids_strict() {
+    let read_ids = load_read_ids(Path::new("test_data/rand_readids_5_missing.txt"), &ReadIdNormaliser::default()).unwrap().read_ids;
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    partition_reads(Some(&read_ids), slow5_fpath, &FilterMode::Odd.into(), true);
+}
+
+#[test]
+fn normalise_read_ids() {
+    let normaliser = ReadIdNormaliser::default();
+    
+    assert!(normaliser.normalise("d62da1d5-971e-4e5d-9465-5715300e8523") == "d62da1d5-971e-4e5d-9465-5715300e8523");
+    assert!(normaliser.normalise("read_d62da1d5-971e-4e5d-9465-5715300e8523") == "d62da1d5-971e-4e5d-9465-5715300e8523");
+    assert!(normaliser.normalise("@d62da1d5-971e-4e5d-9465-5715300e8523 runid=abc ch=416") == "d62da1d5-971e-4e5d-9465-5715300e8523");
+    assert!(normaliser.normalise(">d62da1d5-971e-4e5d-9465-5715300e8523") == "d62da1d5-971e-4e5d-9465-5715300e8523");
+    assert!(normaliser.normalise("  d62da1d5-971e-4e5d-9465-5715300e8523\r") == "d62da1d5-971e-4e5d-9465-5715300e8523");
+    
+    let normaliser = ReadIdNormaliser::from_rules("trim").unwrap();
+    assert!(normaliser.normalise(" @read_a ") == "@read_a");
+    assert!(ReadIdNormaliser::from_rules("none").unwrap() == ReadIdNormaliser::none());
+    assert!(ReadIdNormaliser::from_rules("bogus").is_none());
+}
+
+#[test]
+fn read_list_formats() {
+    let detect = |fpath: &str| ReadListFormat::detect(Path::new(fpath));
+    
+    assert!(detect("test_data/rand_readids_5.txt") == ReadListFormat::Text);
+    assert!(detect("test_data/rand_reads_5.fastq.gz") == ReadListFormat::Fastq);
+    assert!(detect("test_data/rand_reads_5.sam") == ReadListFormat::Sam);
+    assert!(detect("test_data/rand_reads_5.bam") == ReadListFormat::Bam);
+    assert!(detect("test_data/rand_reads_5.paf") == ReadListFormat::Paf);
+    assert!(detect("test_data/sequencing_summary_5.txt") == ReadListFormat::SeqSummary);
+}
+
+#[test]
+fn read_list_from_alignments() {
+    let normaliser = ReadIdNormaliser::default();
+    
+    for fpath in ["test_data/rand_reads_5.sam", "test_data/rand_reads_5.bam"] {
+        let read_id_list = load_read_ids(Path::new(fpath), &normaliser).unwrap();
+        assert!(read_id_list.read_ids.len() == 5);
+        assert!(read_id_list.read_ids[0] == "d62da1d5-971e-4e5d-9465-5715300e8523");
+        assert!(read_id_list.read_ids[2] == "d56f390f-2e33-436e-9220-a93aca7dd11b");
+        assert!(read_id_list.rewritten == 0);
+    }
+    
+    let read_id_list = load_read_ids(Path::new("test_data/rand_reads_5.paf"), &normaliser).unwrap();
+    assert!(read_id_list.read_ids.len() == 4);
+    assert!(read_id_list.read_ids[3] == "76b715cd-aaea-4ae1-8026-41c1772597ed");
+}
+
+#[test]
+fn read_list_from_fastq_and_summary() {
+    let normaliser = ReadIdNormaliser::default();
+    
+    let read_id_list = load_read_ids(Path::new("test_data/rand_reads_5.fastq.gz"), &normaliser).unwrap();
+    assert!(read_id_list.read_ids.len() == 3);
+    assert!(read_id_list.read_ids[1] == "8bfec45c-b89e-4510-9469-e94bb415b8e4");
+    assert!(read_id_list.rewritten == 0);
+    
+    let read_id_list = load_read_ids(Path::new("test_data/sequencing_summary_5.txt"), &normaliser).unwrap();
+    assert!(read_id_list.read_ids.len() == 5);
+    assert!(read_id_list.read_ids[4] == "76b715cd-aaea-4ae1-8026-41c1772597ed");
+}
+
+#[test]
+fn read_list_from_multiline_fastq() {
+    let fastq_fpath = std::env::temp_dir().join(format!("bad_reads_multiline_{}.fastq", std::process::id()));
+    let truncated_fpath = std::env::temp_dir().join(format!("bad_reads_truncated_{}.fastq", std::process::id()));
+    std::fs::write(&fastq_fpath, "@read_a desc\nACGT\nAC\n+\n@@@@\n@@\n\n@read_b\nAC\n+read_b\n@!\n").unwrap();
+    std::fs::write(&truncated_fpath, "@read_a\nACGT\n+\n@@\n").unwrap();
+    
+    let format = ReadListFormat::detect(&fastq_fpath);
+    let read_id_list = load_read_ids_as(&fastq_fpath, format, &ReadIdNormaliser::none());
+    let truncated = load_read_ids_as(&truncated_fpath, ReadListFormat::Fastq, &ReadIdNormaliser::none());
+    std::fs::remove_file(&fastq_fpath).unwrap();
+    std::fs::remove_file(&truncated_fpath).unwrap();
+    
+    assert!(format == ReadListFormat::Fastq);
+    assert!(read_id_list.unwrap().read_ids == ["read_a", "read_b"]);
+    assert!(truncated.is_err_and(|err| err.kind() == std::io::ErrorKind::InvalidData));
+}
+
+#[test]
+#[should_panic(expected = "invalid BAM record 0: empty read name")]
+fn bam_record_without_read_name() {
+    // uncompressed BAM without text or references, then a record of zeroed fixed fields
+    let mut bam = b"BAM\x01".to_vec();
+    bam.extend_from_slice(&[0; 8]);
+    bam.extend_from_slice(&32i32.to_le_bytes());
+    bam.extend_from_slice(&[0; 32]);
+    let bam_fpath = std::env::temp_dir().join(format!("bad_reads_no_read_name_{}.bam", std::process::id()));
+    std::fs::write(&bam_fpath, bam).unwrap();
+    
+    let mut alignments = Vec::new();
+    let read = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| read_alignments(&bam_fpath, |alignment| alignments.push(alignment))));
+    std::fs::remove_file(&bam_fpath).unwrap();
+    if let Err(err) = read {
+        std::panic::resume_unwind(err);
+    }
+}
+
+#[test]
+#[should_panic(expected = "invalid BAM record 0: invalid cigar op 15")]
+fn bam_record_with_invalid_cigar_op() {
+    let mut record = vec![0u8; 32];
+    record[8] = 2;
+    record[12] = 1;
+    record.extend_from_slice(b"a\0");
+    record.extend_from_slice(&((10 << 4) | 15u32).to_le_bytes());
+    let mut bam = b"BAM\x01".to_vec();
+    bam.extend_from_slice(&[0; 8]);
+    bam.extend_from_slice(&(record.len() as i32).to_le_bytes());
+    bam.extend_from_slice(&record);
+    let bam_fpath = std::env::temp_dir().join(format!("bad_reads_invalid_cigar_{}.bam", std::process::id()));
+    std::fs::write(&bam_fpath, bam).unwrap();
+    
+    let read = std::panic::catch_unwind(|| read_alignments(&bam_fpath, |_| {}));
+    std::fs::remove_file(&bam_fpath).unwrap();
+    if let Err(err) = read {
+        std::panic::resume_unwind(err);
+    }
+}
+
+#[test]
+fn alignment_records() {
+    let mut alignments = Vec::new();
+    read_alignments(Path::new("test_data/rand_reads_5.bam"), |alignment| alignments.push(alignment));
+    
+    assert!(alignments.len() == 7);
+    assert!(alignments[0].target.as_deref() == Some("SIRV1"));
+    assert!(alignments[0].primary);
+    assert!(alignments[0].query_len == 105);
+    assert!(alignments[0].soft_clipped == 10);
+    assert!(alignments[0].matches == Some(86));
+    assert!(alignments[1].target.as_deref() == Some("SIRV2"));
+    assert!(!alignments[1].primary);
+    assert!(!alignments[3].is_mapped());
+    assert!(alignments[3].query_len == 80);
+    assert!(!alignments[5].primary);
+    
+    let mut sam_alignments = Vec::new();
+    read_alignments(Path::new("test_data/rand_reads_5.sam"), |alignment| sam_alignments.push(alignment));
+    
+    for (bam, sam) in alignments.iter().zip(sam_alignments.iter()) {
+        assert!(bam.read_id == sam.read_id);
+        assert!(bam.target == sam.target);
+        assert!(bam.primary == sam.primary);
+        assert!(bam.mapq == sam.mapq);
+        assert!(bam.query_aligned == sam.query_aligned);
+        assert!(bam.block_len == sam.block_len);
+        assert!(bam.matches == sam.matches);
+    }
+}
+
+#[test]
+fn value_range_parse() {
+    assert!(ValueRange::parse("1:2") == Some(ValueRange { min: Some(1.0), max: Some(2.0) }));
+    assert!(ValueRange::parse(":2.5") == Some(ValueRange { min: None, max: Some(2.5) }));
+    assert!(ValueRange::parse("3:") == Some(ValueRange { min: Some(3.0), max: None }));
+    assert!(ValueRange::parse("3").is_none());
+    assert!(ValueRange::parse("a:1").is_none());
+}
+
 #[test]
 fn read_timestamps() {
     let read_timestamps = gen_read_timestamps(Path::new("test_data/rand_reads_5.blow5"));
@@ -55,6 +299,109 @@ fn read_timestamps() {
     assert!(read_timestamps[4].pore == 1);
 }
 
+#[test]
+fn read_timestamps_threaded() {
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let read_timestamps = gen_read_timestamps(slow5_fpath);
+    
+    for threads in [2, 3, 8] {
+        let read_timestamps_par = gen_read_timestamps_with(slow5_fpath, &ScanOptions { threads, meta_only: false, ..Default::default() });
+        
+        assert!(read_timestamps_par.len() == read_timestamps.len());
+        for (a, b) in read_timestamps.iter().zip(read_timestamps_par.iter()) {
+            assert!(a.read_id == b.read_id);
+            assert!(a.secs_start == b.secs_start);
+            assert!(a.channel == b.channel);
+            assert!(a.pore == b.pore);
+        }
+    }
+}
+
+#[test]
+fn read_timestamps_meta_only() {
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let read_timestamps = gen_read_timestamps_with(slow5_fpath, &ScanOptions { threads: 1, meta_only: false, ..Default::default() });
+    
+    for threads in [1, 2] {
+        let read_timestamps_meta = gen_read_timestamps_with(slow5_fpath, &ScanOptions { threads, meta_only: true, ..Default::default() });
+        
+        assert!(read_timestamps_meta.len() == read_timestamps.len());
+        for (a, b) in read_timestamps.iter().zip(read_timestamps_meta.iter()) {
+            assert!(a.read_id == b.read_id);
+            assert!(a.secs_start == b.secs_start);
+            assert!(a.channel == b.channel);
+            assert!(a.pore == b.pore);
+        }
+        
+        // len_signal comes from the svb-zd block header without decoding
+        let read_metas = scan_read_meta(slow5_fpath, &ScanOptions { threads, meta_only: false, ..Default::default() });
+        let read_metas_meta = scan_read_meta(slow5_fpath, &ScanOptions { threads, meta_only: true, ..Default::default() });
+        assert!(read_metas.len() == 5 && read_metas_meta == read_metas);
+    }
+}
+
+#[test]
+fn read_meta_cache_round_trip() {
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let cache_fpath = std::env::temp_dir().join(format!("bad_reads_cache_{}.timestamps", std::process::id()));
+    let read_metas = scan_read_meta(slow5_fpath, &ScanOptions::default());
+    
+    assert!(read_meta_cache(&cache_fpath, slow5_fpath).is_none());
+    write_meta_cache(&cache_fpath, slow5_fpath, &read_metas).unwrap();
+    
+    let cached = read_meta_cache(&cache_fpath, slow5_fpath);
+    let mut cache = std::fs::read(&cache_fpath).unwrap();
+    cache.push(0);
+    std::fs::write(&cache_fpath, cache).unwrap();
+    let trailing = read_meta_cache(&cache_fpath, slow5_fpath);
+    std::fs::remove_file(&cache_fpath).unwrap();
+    
+    assert!(cached == Some(read_metas));
+    assert!(trailing.is_none());
+    // keyed by the slow5 it was built from
+    assert!(cache_path(slow5_fpath) == Path::new("test_data/rand_reads_5.blow5.timestamps"));
+}
+
+#[test]
+fn truncated_cache_is_rebuilt() {
+    let slow5_fpath = std::env::temp_dir().join(format!("bad_reads_truncated_cache_{}.blow5", std::process::id()));
+    std::fs::copy("test_data/rand_reads_5.blow5", &slow5_fpath).unwrap();
+    let cache_fpath = cache_path(&slow5_fpath);
+    let read_metas = scan_read_meta(&slow5_fpath, &ScanOptions::default());
+    write_meta_cache(&cache_fpath, &slow5_fpath, &read_metas).unwrap();
+    let cache = std::fs::read(&cache_fpath).unwrap();
+    std::fs::write(&cache_fpath, &cache[..cache.len() - 5]).unwrap();
+    
+    let mut streamed = Vec::new();
+    let opts = ScanOptions { cache: CacheMode::ReadWrite, ..Default::default() };
+    for_each_read_meta(&slow5_fpath, &opts, |meta| streamed.push(meta));
+    let rebuilt = read_meta_cache(&cache_fpath, &slow5_fpath);
+    let cache_name = cache_fpath.file_name().unwrap().to_str().unwrap().to_string();
+    let tmp_left = std::fs::read_dir(std::env::temp_dir()).unwrap()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .any(|name| name.starts_with(&cache_name) && name.ends_with(".tmp"));
+    std::fs::remove_file(&cache_fpath).unwrap();
+    std::fs::remove_file(&slow5_fpath).unwrap();
+    
+    assert!(streamed == read_metas);
+    assert!(rebuilt == Some(read_metas));
+    assert!(!tmp_left);
+}
+
+#[test]
+fn read_timestamps_tie_break() {
+    let mut read_timestamps = vec![
+        ReadTimestamp { read_id: "b".into(), secs_start: 1.0, channel: 2, pore: 1 },
+        ReadTimestamp { read_id: "c".into(), secs_start: 1.0, channel: 1, pore: 2 },
+        ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 2, pore: 1 },
+        ReadTimestamp { read_id: "d".into(), secs_start: 0.5, channel: 9, pore: 9 },
+    ];
+    sort_read_timestamps(&mut read_timestamps);
+    
+    let read_ids = read_timestamps.iter().map(|ts| &ts.read_id).collect::<Vec<&ReadId>>();
+    assert!(read_ids == ["d", "c", "a", "b"]);
+}
+
 #[test]
 fn pore_mux_map() {
     let pore_mux_map = gen_pore_mux_map(Path::new("test_data/pore_scan_test_data.csv"));
@@ -80,9 +427,8 @@ fn one_read_one_bad_mux() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead, ..Default::default() }
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead }
             ],
-            ..Default::default()
         }
     );
     
@@ -90,10 +436,10 @@ fn one_read_one_bad_mux() {
         ReadTimestamp { read_id: "a".into(), secs_start: 0.0, channel: 0, pore: 0 }
     );
     
-    let reads = get_last_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     assert!(!reads.is_empty());
     
-    let reads = get_first_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     assert!(reads.is_empty());
 }
 
@@ -105,9 +451,8 @@ fn one_read_after_bad_mux() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead, ..Default::default() }
+                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead }
             ],
-            ..Default::default()
         }
     );
     
@@ -115,13 +460,33 @@ fn one_read_after_bad_mux() {
         ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 0, pore: 0 }
     );
     
-    let reads = get_last_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     assert!(reads.is_empty());
     
-    let reads = get_first_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     assert!(!reads.is_empty());
 }
 
+#[test]
+fn reads_tied_with_scan_or_each_other() {
+    let mut pore_mux_map = HashMap::new();
+    pore_mux_map.insert((0, 0), PoreMuxStats { muxs: vec![MuxStat { secs_start: 1.0, pore_state: PoreState::Dead }] });
+    
+    // a read starting with the scan is matched on neither side
+    let at_scan = vec![ReadTimestamp { read_id: "x".into(), secs_start: 1.0, channel: 0, pore: 0 }];
+    assert!(get_last_read(&pore_mux_map, &at_scan, PoreState::Dead).is_empty());
+    assert!(get_first_read(&pore_mux_map, &at_scan, PoreState::Dead).is_empty());
+    
+    let read_timestamps = vec![
+        ReadTimestamp { read_id: "a".into(), secs_start: 0.5, channel: 0, pore: 0 },
+        ReadTimestamp { read_id: "b".into(), secs_start: 0.5, channel: 0, pore: 0 },
+        ReadTimestamp { read_id: "c".into(), secs_start: 2.0, channel: 0, pore: 0 },
+        ReadTimestamp { read_id: "d".into(), secs_start: 2.0, channel: 0, pore: 0 },
+    ];
+    assert!(get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead) == [&ReadId::from("b")]);
+    assert!(get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead) == [&ReadId::from("c")]);
+}
+
 #[test]
 fn two_read_one_bad_mux() {
     let mut pore_mux_map = HashMap::new();
@@ -130,16 +495,15 @@ fn two_read_one_bad_mux() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead, ..Default::default() }
+                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead }
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 0.0, channel: 0, pore: 0 });
     read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 1.0, channel: 0, pore: 0 });
     
-    let reads = get_last_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
     assert!(reads.len() == 1);
@@ -154,16 +518,15 @@ fn one_bad_mux_two_read() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead, ..Default::default() }
+                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead }
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 0, pore: 0 });
     read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 2.0, channel: 0, pore: 0 });
     
-    let reads = get_first_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
     assert!(reads.len() == 1);
@@ -178,17 +541,16 @@ fn two_read_two_bad_mux() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead, ..Default::default() },
-                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead, ..Default::default() },
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 0.0, channel: 0, pore: 0 });
     read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 2.0, channel: 0, pore: 0 });
     
-    let reads = get_last_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
     assert!(reads.len() == 2);
@@ -204,17 +566,16 @@ fn two_bad_mux_two_read() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead, ..Default::default() },
-                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead, ..Default::default() },
+                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 0, pore: 0 });
     read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 3.0, channel: 0, pore: 0 });
     
-    let reads = get_first_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
     assert!(reads.len() == 2);
@@ -230,16 +591,15 @@ fn one_bad_read_good_then_bad_mux() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive, ..Default::default() },
-                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead, ..Default::default() },
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive },
+                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 0.0, channel: 0, pore: 0 });
     
-    let reads = get_last_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(reads.is_empty());
 }
@@ -252,16 +612,15 @@ fn bad_then_good_mux_one_bad_read() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead, ..Default::default() },
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive, ..Default::default() },
+                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 2.0, channel: 0, pore: 0 });
     
-    let reads = get_first_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(reads.is_empty());
 }
@@ -274,16 +633,15 @@ fn good_mux_before_read_then_bad() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive, ..Default::default() },
-                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead, ..Default::default() },
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive },
+                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 2.0, channel: 0, pore: 0 });
     
-    let reads = get_last_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
 }
@@ -296,16 +654,15 @@ fn bad_mux_before_read_then_good() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead, ..Default::default() },
-                MuxStat { secs_start: 3.0, pore_state: PoreState::Alive, ..Default::default() },
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 3.0, pore_state: PoreState::Alive },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 2.0, channel: 0, pore: 0 });
     
-    let reads = get_first_read(pore_mux_map.clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
 }
@@ -318,17 +675,16 @@ fn read_good_mux_read_bad_mux() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive, ..Default::default() },
-                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead, ..Default::default() },
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Alive },
+                MuxStat { secs_start: 3.0, pore_state: PoreState::Dead },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 0.0, channel: 0, pore: 0 });
     read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 2.0, channel: 0, pore: 0 });
     
-    let reads = get_last_read(pore_mux_map.clone().clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
     assert!(reads.len() == 1);
@@ -343,19 +699,696 @@ fn good_mux_read_bad_mux_read() {
     pore_mux_map.insert((0, 0),
         PoreMuxStats {
             muxs: vec![
-                MuxStat { secs_start: 0.0, pore_state: PoreState::Alive, ..Default::default() },
-                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead, ..Default::default() },
+                MuxStat { secs_start: 0.0, pore_state: PoreState::Alive },
+                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead },
             ],
-            ..Default::default()
         }
     );
     
     read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 0, pore: 0 });
     read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 3.0, channel: 0, pore: 0 });
     
-    let reads = get_first_read(pore_mux_map.clone().clone(), &read_timestamps, PoreState::Dead);
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
     
     assert!(!reads.is_empty());
     assert!(reads.len() == 1);
     assert!(reads[0] == "b");
 }
+
+#[test]
+fn unsorted_muxs_and_reads() {
+    let mut pore_mux_map = HashMap::new();
+    let mut read_timestamps = Vec::new();
+    
+    pore_mux_map.insert((0, 0),
+        PoreMuxStats {
+            muxs: vec![
+                MuxStat { secs_start: 4.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 0.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead },
+            ],
+        }
+    );
+    
+    for (read_id, secs_start) in [("a", 1.0), ("b", 3.0), ("c", 5.0), ("d", 0.5), ("e", 2.0)] {
+        read_timestamps.push(ReadTimestamp { read_id: read_id.into(), secs_start, channel: 0, pore: 0 });
+    }
+    
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads == ["a", "b"]);
+    
+    // e starts with the second scan, so is not after it
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads == ["d", "b", "c"]);
+}
+
+#[test]
+fn read_at_mux_start() {
+    let mut pore_mux_map = HashMap::new();
+    let mut read_timestamps = Vec::new();
+    
+    pore_mux_map.insert((0, 0),
+        PoreMuxStats {
+            muxs: vec![
+                MuxStat { secs_start: 1.0, pore_state: PoreState::Dead },
+                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead },
+            ],
+        }
+    );
+    
+    read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 0, pore: 0 });
+    read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 1.0, channel: 0, pore: 0 });
+    
+    // reads starting with a scan are not after it, ties keep the read order
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads == ["b"]);
+    
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads.is_empty());
+}
+
+#[test]
+fn many_muxs_per_pore() {
+    let mut pore_mux_map = HashMap::new();
+    let mut read_timestamps = Vec::new();
+    
+    let muxs = (0..1000)
+        .map(|i| MuxStat { secs_start: (i * 10) as f64, pore_state: PoreState::Dead })
+        .collect::<Vec<MuxStat>>();
+    pore_mux_map.insert((0, 0), PoreMuxStats { muxs });
+    
+    for i in 0..10000 {
+        read_timestamps.push(ReadTimestamp { read_id: i.to_string().into(), secs_start: i as f64, channel: 0, pore: 0 });
+    }
+    
+    let reads = get_last_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads.len() == 999);
+    assert!(reads[0] == "9" && reads[998] == "9989");
+    
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads.len() == 1000);
+    assert!(reads[0] == "1" && reads[999] == "9991");
+}
+
+#[test]
+fn compact_read_ids() {
+    let uuid = "d62da1d5-971e-4e5d-9465-5715300e8523";
+    let read_id = ReadId::from(uuid);
+    assert!(matches!(read_id, ReadId::Uuid(_)));
+    assert!(format!("{}", read_id) == uuid);
+    assert!(read_id == uuid);
+    
+    // anything that would not format back unchanged is kept as is
+    for other in ["D62DA1D5-971E-4E5D-9465-5715300E8523", "d62da1d5971e4e5d94655715300e8523", "read_1", ""] {
+        let read_id = ReadId::from(other);
+        assert!(matches!(read_id, ReadId::Other(_)));
+        assert!(format!("{}", read_id) == other);
+    }
+    
+    let mut read_ids = ["b", "8bfec45c-b89e-4510-9469-e94bb415b8e4", "d62da1d5-971e-4e5d-9465-5715300e8523", "9"]
+        .map(ReadId::from);
+    read_ids.sort();
+    assert!(read_ids == ["8bfec45c-b89e-4510-9469-e94bb415b8e4", "9", "b", "d62da1d5-971e-4e5d-9465-5715300e8523"]);
+}
+
+#[test]
+fn match_reads_reuses_pore_map() {
+    let mut pore_mux_map = HashMap::new();
+    let mut read_timestamps = Vec::new();
+    
+    pore_mux_map.insert((2, 1), PoreMuxStats { muxs: vec![MuxStat { secs_start: 2.0, pore_state: PoreState::Dead }] });
+    pore_mux_map.insert((1, 1),
+        PoreMuxStats {
+            muxs: vec![
+                MuxStat { secs_start: 4.0, pore_state: PoreState::Alive },
+                MuxStat { secs_start: 2.0, pore_state: PoreState::Dead },
+            ],
+        }
+    );
+    
+    read_timestamps.push(ReadTimestamp { read_id: "a".into(), secs_start: 1.0, channel: 2, pore: 1 });
+    read_timestamps.push(ReadTimestamp { read_id: "b".into(), secs_start: 1.0, channel: 1, pore: 1 });
+    read_timestamps.push(ReadTimestamp { read_id: "c".into(), secs_start: 3.0, channel: 1, pore: 1 });
+    read_timestamps.push(ReadTimestamp { read_id: "d".into(), secs_start: 5.0, channel: 1, pore: 1 });
+    
+    let matches = match_reads(&pore_mux_map, &read_timestamps, PoreState::Dead, ReadMode::Last);
+    assert!(matches == [
+        Match { read: 1, channel: 1, pore: 1, scan: 1 },
+        Match { read: 0, channel: 2, pore: 1, scan: 0 },
+    ]);
+    
+    let matches = match_reads(&pore_mux_map, &read_timestamps, PoreState::Alive, ReadMode::Last);
+    assert!(matches == [Match { read: 2, channel: 1, pore: 1, scan: 0 }]);
+    
+    let matches = match_reads(&pore_mux_map, &read_timestamps, PoreState::Alive, ReadMode::First);
+    assert!(matches == [Match { read: 3, channel: 1, pore: 1, scan: 0 }]);
+    
+    let reads = get_first_read(&pore_mux_map, &read_timestamps, PoreState::Dead);
+    assert!(reads == ["c"]);
+}
+
+#[test]
+fn external_sort_matches_in_memory() {
+    let gen = |n: u64| (0..n)
+        .map(|i| ReadTimestamp {
+            read_id: if i % 3 == 0 { format!("{:08x}-0000-0000-0000-{:012x}", i, i) } else { format!("r{}", i) }.into(),
+            secs_start: ((i * 7919) % 101) as f64,
+            channel: (i % 5) as u32,
+            pore: (i % 2) as u8,
+        })
+        .collect::<Vec<ReadTimestamp>>();
+    
+    let mut read_timestamps = gen(1000);
+    sort_read_timestamps(&mut read_timestamps);
+    
+    let mut sorter = TimestampSorter::new(Some(100 * std::mem::size_of::<ReadTimestamp>()));
+    for ts in gen(1000) {
+        sorter.push(ts);
+    }
+    assert!(sorter.spilled_runs() >= 9);
+    
+    let sorted = sorter.finish().collect::<Vec<ReadTimestamp>>();
+    assert!(sorted.len() == read_timestamps.len());
+    for (a, b) in read_timestamps.iter().zip(sorted.iter()) {
+        assert!(a.read_id == b.read_id);
+        assert!(a.secs_start == b.secs_start);
+        assert!(a.channel == b.channel);
+        assert!(a.pore == b.pore);
+    }
+}
+
+#[test]
+fn stream_read_timestamps_with_budget() {
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    let read_timestamps = gen_read_timestamps(slow5_fpath);
+    
+    for meta_only in [true, false] {
+        let opts = ScanOptions { meta_only, memory_budget: Some(1), ..Default::default() };
+        let streamed = stream_read_timestamps(slow5_fpath, &opts).collect::<Vec<ReadTimestamp>>();
+        
+        assert!(streamed.len() == read_timestamps.len());
+        for (a, b) in read_timestamps.iter().zip(streamed.iter()) {
+            assert!(a.read_id == b.read_id);
+            assert!(a.secs_start == b.secs_start);
+        }
+    }
+}
+
+#[test]
+fn match_read_stream_same_as_match_reads() {
+    let mut pore_mux_map = HashMap::new();
+    let mut read_timestamps = Vec::new();
+    
+    for channel in 0..3 {
+        let muxs = (0..50)
+            .map(|i| MuxStat { secs_start: (i * 10 + channel) as f64, pore_state: if i % 3 == 0 { PoreState::Alive } else { PoreState::Dead } })
+            .collect::<Vec<MuxStat>>();
+        pore_mux_map.insert((channel, 1), PoreMuxStats { muxs });
+    }
+    for i in 0..2000 {
+        read_timestamps.push(ReadTimestamp { read_id: format!("r{}", i).into(), secs_start: (i % 521) as f64, channel: i % 4, pore: 1 });
+    }
+    sort_read_timestamps(&mut read_timestamps);
+    
+    for read_mode in [ReadMode::First, ReadMode::Last] {
+        let matches = match_reads(&pore_mux_map, &read_timestamps, PoreState::Dead, read_mode);
+        let streamed = match_read_stream(&pore_mux_map, read_timestamps.iter().map(|ts| ReadTimestamp { read_id: ts.read_id.clone(), ..*ts }), PoreState::Dead, read_mode);
+        
+        assert!(!matches.is_empty());
+        assert!(matches.len() == streamed.len());
+        for (m, (streamed_m, ts)) in matches.iter().zip(streamed.iter()) {
+            assert!(m == streamed_m);
+            assert!(read_timestamps[m.read].read_id == ts.read_id);
+        }
+    }
+}
+
+#[test]
+fn bad_read_query_options() {
+    let mut pore_mux_map = HashMap::new();
+    let mut read_timestamps = Vec::new();
+    
+    for channel in [1, 2] {
+        pore_mux_map.insert((channel, 1),
+            PoreMuxStats {
+                muxs: vec![
+                    MuxStat { secs_start: 10.0, pore_state: PoreState::Dead },
+                    MuxStat { secs_start: 20.0, pore_state: PoreState::Alive },
+                ],
+            }
+        );
+    }
+    for (read_id, secs_start) in [("a", 2.0), ("b", 5.0), ("c", 8.0), ("d", 9.0), ("e", 15.0), ("f", 19.0)] {
+        read_timestamps.push(ReadTimestamp { read_id: read_id.into(), secs_start, channel: 1, pore: 1 });
+    }
+    read_timestamps.push(ReadTimestamp { read_id: "g".into(), secs_start: 9.5, channel: 2, pore: 1 });
+    read_timestamps.push(ReadTimestamp { read_id: "h".into(), secs_start: 9.5, channel: 3, pore: 1 });
+    pore_mux_map.insert((4, 1), PoreMuxStats { muxs: vec![MuxStat { secs_start: 10.0, pore_state: PoreState::Dead }] });
+    
+    let result = BadReadQuery::new().reads_per_scan(3).run_on(&pore_mux_map, &read_timestamps);
+    assert!(result.read_ids() == ["b", "c", "d", "g"]);
+    assert!(result.stats == QueryStats {
+        reads: 8,
+        reads_without_scans: 1,
+        dead_scans: ScanCounts { matched: 2, unmatched: 1 },
+        alive_scans: ScanCounts::default(),
+        matches: 4,
+        pores: PoreCounts { both: 2, scans_only: 1, reads_only: 1 },
+    });
+    
+    let result = BadReadQuery::new().reads_per_scan(3).max_gap(1.5).channels([1]).run_on(&pore_mux_map, &read_timestamps);
+    assert!(result.read_ids() == ["d"]);
+    assert!(result.stats.scans(PoreState::Dead) == ScanCounts { matched: 1, unmatched: 0 });
+    assert!(result.stats.reads_without_scans == 1);
+    
+    let query = BadReadQuery::new()
+        .pore_states(&[PoreState::Dead, PoreState::Alive])
+        .mode(ReadMode::First)
+        .reads_per_scan(2);
+    let result = query.run_on(&pore_mux_map, &read_timestamps);
+    assert!(result.read_ids() == ["e", "f"]);
+    
+    let result = query.scan_window(ValueRange::parse("15:").unwrap()).run_on(&pore_mux_map, &read_timestamps);
+    assert!(result.read_ids().is_empty());
+    assert!(result.stats.scans(PoreState::Alive) == ScanCounts { matched: 0, unmatched: 2 });
+    assert!(result.stats.scans(PoreState::Dead) == ScanCounts::default());
+}
+
+#[test]
+fn bad_read_query_run() {
+    let query = BadReadQuery::new()
+        .sources("test_data/rand_reads_5.blow5", "test_data/pore_scan_test_data.csv")
+        .pore_states(&[PoreState::Dead, PoreState::Alive]);
+    
+    let result = query.run();
+    let streamed = query.clone().scan_options(ScanOptions { memory_budget: Some(1), ..Default::default() }).run();
+    let (with_controls, _) = query.run_with_controls(&ControlOptions::default());
+    
+    assert!(result.stats.reads == 5);
+    assert!(result.stats == streamed.stats && result.stats == with_controls.stats);
+    assert!(result.read_ids() == streamed.read_ids() && result.read_ids() == with_controls.read_ids());
+}
+
+#[test]
+fn json_log_lines() {
+    let log_fpath = std::env::temp_dir().join(format!("bad_reads_log_{}.jsonl", std::process::id()));
+    set_json_log(&log_fpath).unwrap();
+    
+    log_json(LogLevel::Info, "progress", "scanning \"slow5\"", &[("records", 5.0), ("records_per_sec", f64::NAN)]);
+    log_json(LogLevel::Debug, "progress", "not logged", &[]);
+    
+    let log = std::fs::read_to_string(&log_fpath).unwrap();
+    std::fs::remove_file(&log_fpath).unwrap();
+    
+    let line = log.lines().find(|line| line.contains("\"event\":\"progress\"")).expect("missing progress event");
+    assert!(line.starts_with("{\"ts\":") && line.ends_with('}'));
+    assert!(line.contains("\"level\":\"info\",\"event\":\"progress\",\"msg\":\"scanning \\\"slow5\\\"\",\"records\":5,\"records_per_sec\":null"));
+    assert!(!log.contains("not logged"));
+}
+
+#[test]
+fn signal_features_pa() {
+    // (raw + 10) * 100 / 1000 pA at 4 Hz
+    let raw = [0, 90, 190, 290, 390, 999, 190, 90];
+    let opts = FeatureOptions { edge_secs: 0.5, ..Default::default() };
+    let features = signal_features("read", &raw, 1000.0, 10.0, 100.0, 4.0, &opts);
+    
+    assert!(features.samples == 8 && features.duration == 2.0);
+    assert!(features.min == 1.0 && features.max == 100.9);
+    assert!((features.median - 20.0).abs() < 1e-9);
+    assert!((features.mad - 10.0).abs() < 1e-9);
+    assert!((features.start_mean - 5.5).abs() < 1e-9);
+    assert!((features.end_mean - 15.0).abs() < 1e-9);
+    assert!(features.saturated_frac == 0.25);
+    
+    let opts = FeatureOptions { saturation: ValueRange::parse("5:40"), ..opts };
+    let features = signal_features("read", &raw, 1000.0, 10.0, 100.0, 4.0, &opts);
+    assert!(features.saturated_frac == 0.375);
+}
+
+#[test]
+fn extract_features_from_slow5() {
+    let slow5_fpath = Path::new("test_data/rand_reads_5.blow5");
+    
+    let mut all = Vec::new();
+    let missing = extract_features(slow5_fpath, None, &FeatureOptions::default(), |features| all.push(features));
+    assert!(all.len() == 5 && missing.is_empty());
+    assert!(all.iter().all(|features| features.samples > 0 && features.min <= features.median && features.median <= features.max));
+    
+    let read_ids = vec![all[2].read_id.clone(), "not_a_read".to_string()];
+    let mut listed = Vec::new();
+    let missing = extract_features(slow5_fpath, Some(&read_ids), &FeatureOptions::default(), |features| listed.push(features));
+    assert!(missing == ["not_a_read"]);
+    assert!(listed.len() == 1 && listed[0].tsv_row() == all[2].tsv_row());
+    assert!(listed[0].tsv_row().split('\t').count() == FEATURES_TSV_HEADER.split('\t').count());
+}
+
+#[test]
+fn sample_controls_from_alive_pores() {
+    let mut pore_mux_map = HashMap::new();
+    pore_mux_map.insert((1, 1), PoreMuxStats { muxs: vec![
+        MuxStat { secs_start: 200.0, pore_state: PoreState::Dead },
+        MuxStat { secs_start: 0.0, pore_state: PoreState::Alive },
+        MuxStat { secs_start: 100.0, pore_state: PoreState::Alive },
+    ] });
+    pore_mux_map.insert((2, 1), PoreMuxStats { muxs: vec![
+        MuxStat { secs_start: 0.0, pore_state: PoreState::Dead },
+        MuxStat { secs_start: 100.0, pore_state: PoreState::Alive },
+    ] });
+    
+    let meta = |read_id: &str, channel: u32, secs: u64, len_signal: u64| ReadMeta {
+        read_id: read_id.into(),
+        channel,
+        mux: 1,
+        samples_start: secs,
+        sampling_rate: 1.0,
+        len_signal,
+    };
+    let read_metas = vec![
+        meta("case", 3, 50, 1000),
+        meta("near", 1, 40, 1000),
+        meta("long", 1, 60, 5000),
+        meta("dead_after", 1, 150, 1000),
+        meta("dead_before", 2, 50, 1000),
+        meta("no_scan_after", 1, 500, 1000),
+        meta("at_scan", 1, 100, 1000),
+        meta("edge", 1, 99, 1100),
+    ];
+    let selected = vec![ReadTimestamp::from(read_metas[0].clone())];
+    
+    let opts = ControlOptions { per_read: 2, length_tolerance: Some(0.2), seed: 7, ..Default::default() };
+    let controls = sample_controls(&pore_mux_map, &read_metas, &selected, &opts);
+    let mut read_ids = controls.reads.iter().map(|control| control.read_id.to_string()).collect::<Vec<String>>();
+    read_ids.sort();
+    
+    assert!(controls.eligible == 3);
+    assert!(read_ids == ["edge", "near"] && controls.short == 0);
+    assert!(controls.reads.iter().all(|control| control.case == "case"));
+    
+    let opts = ControlOptions { per_read: 3, ..opts };
+    assert!(sample_controls(&pore_mux_map, &read_metas, &selected, &opts).short == 1);
+    
+    let opts = ControlOptions { per_read: 1, time_tolerance: 5.0, ..opts };
+    let controls = sample_controls(&pore_mux_map, &read_metas, &selected, &opts);
+    assert!(controls.reads.is_empty() && controls.short == 1);
+    
+    let opts = ControlOptions { per_read: 1, length_tolerance: None, ..Default::default() };
+    let first = sample_controls(&pore_mux_map, &read_metas, &selected, &opts);
+    let again = sample_controls(&pore_mux_map, &read_metas, &selected, &opts);
+    assert!(first.reads[0].read_id == again.reads[0].read_id);
+}
+
+#[test]
+fn pore_lifetimes_and_survival() {
+    let scan = |secs_start: f64, alive: bool| MuxStat { secs_start, pore_state: if alive { PoreState::Alive } else { PoreState::Dead } };
+    let mut pore_mux_map = HashMap::new();
+    pore_mux_map.insert((1, 1), PoreMuxStats { muxs: vec![scan(20.0, false), scan(0.0, true), scan(10.0, true), scan(30.0, false)] });
+    pore_mux_map.insert((1, 2), PoreMuxStats { muxs: vec![scan(0.0, true), scan(10.0, false), scan(20.0, true)] });
+    pore_mux_map.insert((2, 1), PoreMuxStats { muxs: vec![scan(0.0, true), scan(10.0, false)] });
+    pore_mux_map.insert((2, 2), PoreMuxStats { muxs: vec![scan(0.0, false)] });
+    pore_mux_map.insert((3, 1), PoreMuxStats { muxs: vec![scan(0.0, true), scan(20.0, false)] });
+    
+    let lifetimes = pore_lifetimes(&pore_mux_map);
+    assert!(lifetimes.iter().map(|lifetime| (lifetime.channel, lifetime.mux)).collect::<Vec<_>>() == [(1, 1), (1, 2), (2, 1), (2, 2), (3, 1)]);
+    assert!(lifetimes[0].death == Some(20.0) && lifetimes[0].lifetime() == Some(20.0));
+    assert!(lifetimes[1].is_censored() && lifetimes[1].lifetime() == Some(20.0));
+    assert!(lifetimes[2].death == Some(10.0));
+    assert!(lifetimes[3].last_alive.is_none() && lifetimes[3].lifetime().is_none() && !lifetimes[3].is_censored());
+    
+    // deaths at 10, 20, 20 and a censoring at 20, out of 4 pores
+    let curve = kaplan_meier(&lifetimes);
+    assert!(curve.len() == 2);
+    assert!(curve[0].secs == 10.0 && curve[0].at_risk == 4 && curve[0].deaths == 1 && curve[0].survival == 0.75);
+    assert!(curve[1].secs == 20.0 && curve[1].at_risk == 3 && curve[1].deaths == 2 && curve[1].censored == 1);
+    assert!((curve[1].survival - 0.25).abs() < 1e-12);
+    assert!((curve[0].std_err - (0.75f64 * 0.25 / 4.0).sqrt()).abs() < 1e-12);
+}
+
+#[test]
+fn flowcell_layouts() {
+    for layout in [FlowcellLayout::MinIon, FlowcellLayout::Flongle, FlowcellLayout::PromethIon] {
+        let (rows, cols) = layout.dims();
+        assert!(rows * cols == layout.channels());
+        
+        let mut seen = std::collections::HashSet::new();
+        for channel in 1..=layout.channels() {
+            let pos = layout.position(channel).unwrap();
+            assert!(seen.insert(pos));
+            assert!(layout.channel_at(pos) == Some(channel));
+        }
+        assert!(layout.position(0).is_none() && layout.position(layout.channels() + 1).is_none());
+    }
+    
+    let minion = FlowcellLayout::MinIon;
+    assert!(minion.position(33) == Some(ChannelPos { row: 0, col: 0 }));
+    assert!(minion.position(8) == Some(ChannelPos { row: 8, col: 0 }));
+    assert!(minion.position(1) == Some(ChannelPos { row: 15, col: 0 }));
+    let mut neighbours = minion.neighbours(40);
+    neighbours.sort();
+    assert!(neighbours == [8, 39, 48]);
+    assert!(FlowcellLayout::PromethIon.position(251) == Some(ChannelPos { row: 0, col: 10 }));
+    assert!(FlowcellLayout::PromethIon.block(3000, 5, 10) == Some(4 * 12 + 11));
+    
+    assert!(FlowcellLayout::detect(Some("minion"), Some("FLO-FLG114")) == Some(FlowcellLayout::Flongle));
+    assert!(FlowcellLayout::detect(Some("p2_solo"), None) == Some(FlowcellLayout::PromethIon));
+    assert!(FlowcellLayout::detect(None, None).is_none());
+    assert!(read_flowcell_layout(Path::new("test_data/rand_reads_5.blow5")) == Some(FlowcellLayout::MinIon));
+}
+
+#[test]
+fn morans_i_of_dead_pores() {
+    let layout = FlowcellLayout::Flongle;
+    let (rows, cols) = layout.dims();
+    let channel = |row: u32, col: u32| layout.channel_at(ChannelPos { row, col }).unwrap();
+    
+    // left half dead, right half alive
+    let clustered = (0..rows).flat_map(|row| (0..cols).map(move |col| (row, col)))
+        .map(|(row, col)| (channel(row, col), if col < cols / 2 { 1.0 } else { 0.0 }))
+        .collect::<Vec<(u32, f64)>>();
+    let test = moran_test(layout, &clustered, 199, 1);
+    assert!(test.channels == 126 && test.morans_i > 0.8);
+    assert!(test.p_value == 1.0 / 200.0);
+    assert!(test == moran_test(layout, &clustered, 199, 1));
+    
+    // checkerboard
+    let dispersed = clustered.iter()
+        .map(|(channel, _)| {
+            let pos = layout.position(*channel).unwrap();
+            (*channel, ((pos.row + pos.col) % 2) as f64)
+        })
+        .collect::<Vec<(u32, f64)>>();
+    let test = moran_test(layout, &dispersed, 99, 1);
+    assert!((test.morans_i + 1.0).abs() < 1e-9 && test.p_value == 1.0);
+    
+    assert!(moran_test(layout, &[(1, 1.0), (2, 1.0)], 9, 0).morans_i.is_nan());
+    
+    let scan = |secs_start: f64, alive: bool| MuxStat { secs_start, pore_state: if alive { PoreState::Alive } else { PoreState::Dead } };
+    let mut pore_mux_map = HashMap::new();
+    pore_mux_map.insert((1, 1), PoreMuxStats { muxs: vec![scan(10.0, false), scan(0.0, true)] });
+    pore_mux_map.insert((1, 2), PoreMuxStats { muxs: vec![scan(1.0, true), scan(11.0, true)] });
+    pore_mux_map.insert((2, 1), PoreMuxStats { muxs: vec![scan(2.0, false)] });
+    pore_mux_map.insert((999, 1), PoreMuxStats { muxs: vec![scan(2.0, false)] });
+    
+    let deaths = channel_deaths(&pore_mux_map, layout);
+    assert!(deaths.len() == 2);
+    assert!(deaths[0].channel == 1 && deaths[0].pores == 2 && deaths[0].died == 1 && deaths[0].dead_frac() == 0.5);
+    assert!(deaths[1].never_alive == 1 && deaths[1].dead_frac().is_nan());
+    
+    let rounds = scan_rounds(&pore_mux_map);
+    assert!(rounds.len() == 2 && rounds[0].secs == 0.0 && rounds[1].secs == 10.0);
+    assert!(rounds[0].values == [(1, 0.0), (2, 1.0), (999, 1.0)]);
+    assert!(rounds[1].values == [(1, 0.5)]);
+}
+
+#[test]
+fn flowcell_svgs() {
+    let layout = FlowcellLayout::Flongle;
+    let mut pore_mux_map = HashMap::new();
+    pore_mux_map.insert((1, 1), PoreMuxStats { muxs: vec![MuxStat { secs_start: 5.0, pore_state: PoreState::Alive }] });
+    pore_mux_map.insert((1, 2), PoreMuxStats { muxs: vec![MuxStat { secs_start: 5.0, pore_state: PoreState::Dead }] });
+    
+    let rounds = scan_rounds(&pore_mux_map);
+    let svg = scan_round_svg(layout, &rounds[0]);
+    assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+    assert!(svg.contains("<title>channel 1 mux 1: alive</title>") && svg.contains("<title>channel 1 mux 2: dead</title>"));
+    assert!(svg.matches("not scanned</title>").count() == 126 * 4 - 2);
+    
+    let read_metas = ["a", "b", "c"].iter().zip([3, 3, 7])
+        .map(|(read_id, channel)| ReadMeta { read_id: (*read_id).into(), channel, mux: 1, samples_start: 0, sampling_rate: 1.0, len_signal: 1 })
+        .collect::<Vec<ReadMeta>>();
+    let selected = [ReadId::from("c")].into_iter().collect::<std::collections::HashSet<ReadId>>();
+    let counts = reads_per_channel(&read_metas, None);
+    assert!(counts[&3] == 2 && counts[&7] == 1);
+    assert!(reads_per_channel(&read_metas, Some(&selected)) == HashMap::from([(7, 1)]));
+    
+    let svg = channel_counts_svg(layout, "reads <all>", &counts);
+    assert!(svg.contains("reads &lt;all&gt;"));
+    assert!(svg.contains("fill=\"#08306b\" stroke=\"#cccccc\" stroke-width=\"0.5\"><title>channel 3: 2</title>"));
+    assert!(svg.contains("<title>channel 4: 0</title>"));
+}
+
+#[test]
+fn fisher_exact_and_bh() {
+    // R: fisher.test(matrix(c(1, 9, 11, 3), nrow = 2))
+    assert!((fisher_exact(1, 9, 11, 3) - 0.002759).abs() < 1e-6);
+    assert!((fisher_exact(3, 1, 1, 3) - 0.485714).abs() < 1e-6);
+    assert!((fisher_exact(5, 5, 5, 5) - 1.0).abs() < 1e-12);
+    assert!(fisher_exact(0, 1_000_000, 500, 2_000_000) < 1e-50);
+    
+    let q_values = bh_adjust(&[0.01, 0.04, 0.03, 0.5]);
+    let expected = [0.04, 0.16 / 3.0, 0.16 / 3.0, 0.5];
+    assert!(q_values.iter().zip(expected).all(|(q, expected)| (q - expected).abs() < 1e-12));
+}
+
+#[test]
+fn compare_count_files() {
+    let dir = std::env::temp_dir();
+    let all_fpath = dir.join(format!("bad_reads_all_counts_{}.txt", std::process::id()));
+    let selected_fpath = dir.join(format!("bad_reads_selected_counts_{}.txt", std::process::id()));
+    std::fs::write(&all_fpath, "     90 geneA\n     10 geneB\n      5 geneC\n").unwrap();
+    std::fs::write(&selected_fpath, "1 geneA\n9 geneB\n2 geneD\n").unwrap();
+    
+    let all = load_counts(&all_fpath, 0);
+    let selected = load_counts(&selected_fpath, 0);
+    std::fs::remove_file(&all_fpath).unwrap();
+    std::fs::remove_file(&selected_fpath).unwrap();
+    
+    let comparisons = compare_counts(&all, &selected);
+    let rows = comparisons.iter().map(|comparison| (comparison.name.as_str(), comparison.all, comparison.selected)).collect::<Vec<_>>();
+    assert!(rows == [("geneA", 90, 1), ("geneB", 10, 9), ("geneC", 5, 0), ("geneD", 0, 2)]);
+    assert!(comparisons[1].log2_ratio > 2.0 && comparisons[1].q_value < 0.001);
+    assert!(comparisons[0].log2_ratio < 0.0);
+    assert!(comparisons.iter().all(|comparison| comparison.q_value >= comparison.p_value));
+    
+    let svg = count_scatter_svg("all vs selected", &comparisons, 0.05);
+    assert!(svg.matches("<circle ").count() == 4 && svg.contains("<title>geneB: 9 / 10"));
+}
+
+#[test]
+fn count_reads_per_reference() {
+    let counts = |fpath: &str, read_ids: Option<&std::collections::HashSet<ReadId>>, opts: &CountOptions| count_reads(Path::new(fpath), read_ids, opts);
+    
+    let sam = counts("test_data/rand_reads_5.sam", None, &CountOptions::default());
+    assert!(sam.counts == [("SIRV1".to_string(), 2), ("SIRV2".to_string(), 1), ("SIRV3".to_string(), 1)]);
+    assert!(sam.alignments == 7 && sam.unmapped == 1 && sam.filtered == 2);
+    
+    let all_alignments = counts("test_data/rand_reads_5.sam", None, &CountOptions { primary_only: false, min_mapq: 10, ..Default::default() });
+    assert!(all_alignments.counts == [("SIRV3".to_string(), 2), ("SIRV1".to_string(), 1), ("SIRV2".to_string(), 1)]);
+    
+    let read_ids = ["503f0bd8-3a00-4c76-9f2e-c70ada3d418b", "76b715cd-aaea-4ae1-8026-41c1772597ed"].iter()
+        .map(|read_id| ReadId::from(*read_id))
+        .collect::<std::collections::HashSet<ReadId>>();
+    let gene_map = HashMap::from([("SIRV1".to_string(), "geneA".to_string()), ("SIRV3".to_string(), "geneA".to_string())]);
+    let opts = CountOptions { min_aligned_len: 50, gene_map: Some(gene_map), ..Default::default() };
+    let paf = counts("test_data/rand_reads_5.paf", Some(&read_ids), &opts);
+    assert!(paf.counts == [("geneA".to_string(), 2)]);
+    assert!(paf.alignments == 3 && paf.unlisted == 0);
+}
+
+#[test]
+fn mann_whitney_u() {
+    // scipy: mannwhitneyu([1, 2, 3], [4, 5, 6], method="asymptotic")
+    let test = mann_whitney(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]);
+    assert!(test.u == 0.0 && (test.p_value - 0.080856).abs() < 1e-5);
+    assert!(mann_whitney(&[6.0, 5.0, 4.0], &[1.0, 2.0, 3.0]).u == 9.0);
+    assert!(mann_whitney(&[2.0, 2.0], &[2.0, f64::NAN]).p_value == 1.0);
+    assert!(mann_whitney(&[1.0], &[]).p_value.is_nan());
+}
+
+#[test]
+fn align_qc_of_groups() {
+    let dir = std::env::temp_dir();
+    let controls_fpath = dir.join(format!("bad_reads_align_qc_controls_{}.tsv", std::process::id()));
+    std::fs::write(
+        &controls_fpath,
+        "read_id\tlabel\tcase_read_id\n\
+         d62da1d5-971e-4e5d-9465-5715300e8523\tcase\td62da1d5-971e-4e5d-9465-5715300e8523\n\
+         d56f390f-2e33-436e-9220-a93aca7dd11b\tcontrol\td62da1d5-971e-4e5d-9465-5715300e8523\n\
+         503f0bd8-3a00-4c76-9f2e-c70ada3d418b\tcontrol\td62da1d5-971e-4e5d-9465-5715300e8523\n",
+    ).unwrap();
+    let cases = load_labelled_read_ids(&controls_fpath, "case").unwrap();
+    let controls = load_labelled_read_ids(&controls_fpath, "control").unwrap();
+    std::fs::remove_file(&controls_fpath).unwrap();
+    assert!(cases.len() == 1 && controls.len() == 2);
+    assert!(load_labelled_read_ids(Path::new("test_data/rand_readids_5.txt"), "case").is_none());
+    
+    let reads = cases.into_iter().map(|read_id| (read_id, ReadGroup::Selected))
+        .chain(controls.into_iter().map(|read_id| (read_id, ReadGroup::Control)))
+        .collect::<Vec<(String, ReadGroup)>>();
+    
+    // minimap2 --paf-no-hit writes unmapped reads with target `*`
+    let no_hit_fpath = dir.join(format!("bad_reads_align_qc_no_hit_{}.paf", std::process::id()));
+    let mut paf = std::fs::read_to_string("test_data/rand_reads_5.paf").unwrap();
+    paf.push_str("d56f390f-2e33-436e-9220-a93aca7dd11b\t80\t0\t0\t*\t*\t0\t0\t0\t0\t0\t0\trl:i:0\n");
+    std::fs::write(&no_hit_fpath, paf).unwrap();
+    
+    // the unmapped read has a record in the sam but not in the paf
+    for (fpath, unmapped_records) in [("test_data/rand_reads_5.sam", 1), ("test_data/rand_reads_5.paf", 0), (no_hit_fpath.to_str().unwrap(), 1)] {
+        let qcs = align_qc(Path::new(fpath), &reads);
+        assert!(qcs[0].target.as_deref() == Some("SIRV1") && qcs[0].mapq == 60 && qcs[0].records == 2);
+        assert!(!qcs[1].is_mapped() && qcs[1].records == unmapped_records);
+        assert!(qcs[2].target.as_deref() == Some("SIRV1") && qcs[2].clipped_fraction == Some(0.5));
+        
+        let comparisons = compare_align_qc(&qcs);
+        assert!(comparisons.iter().map(|comparison| comparison.metric).collect::<Vec<_>>() == ["mapped", "mapq", "identity", "aligned_fraction", "clipped_fraction"]);
+        assert!(comparisons[0].selected.mean == 1.0 && comparisons[0].control.mean == 0.5 && comparisons[0].control.n == 2);
+        assert!(comparisons[4].selected.n == 1 && comparisons[4].control.median == 0.5);
+    }
+    std::fs::remove_file(&no_hit_fpath).unwrap();
+}
+
+#[test]
+fn basecall_qc_from_summary() {
+    let summary_fpath = Path::new("test_data/sequencing_summary_5.txt");
+    let mut summary = Vec::new();
+    read_seq_summary(summary_fpath, |read| summary.push(read));
+    assert!(summary.len() == 5);
+    assert!(summary[2].passes_filtering == Some(false) && summary[2].length == Some(80) && summary[2].qscore == Some(6.2));
+    
+    let read_ids = |read_ids: &[&str]| read_ids.iter().map(|read_id| ReadId::from(*read_id)).collect::<std::collections::HashSet<ReadId>>();
+    let selected = read_ids(&["d62da1d5-971e-4e5d-9465-5715300e8523", "8bfec45c-b89e-4510-9469-e94bb415b8e4", "not-in-summary"]);
+    
+    let report = compare_basecalls(summary_fpath, &selected, None);
+    assert!(report.selected == 2 && report.control == 3 && report.missing == 1);
+    assert!(report.comparisons.iter().map(|comparison| comparison.metric).collect::<Vec<_>>() == ["qscore", "passes_filtering", "length"]);
+    let length = report.comparisons[2].control;
+    assert!(length.n == 3 && length.median == 100.0 && length.q25 == 90.0 && length.q75 == 110.0);
+    assert!(report.comparisons[1].selected.mean == 1.0 && report.comparisons[1].control.median.is_nan());
+    assert!(report.to_json().contains("\"metric\":\"passes_filtering\",\"selected\":{\"n\":2,\"mean\":1,\"median\":null"));
+    
+    let controls = read_ids(&["503f0bd8-3a00-4c76-9f2e-c70ada3d418b"]);
+    let report = compare_basecalls(summary_fpath, &selected, Some(&controls));
+    assert!(report.control == 1 && report.comparisons[0].control.mean == 9.8);
+    
+    // a summary concatenated with itself counts every read once
+    let doubled_fpath = std::env::temp_dir().join(format!("bad_reads_doubled_summary_{}.txt", std::process::id()));
+    let summary = std::fs::read_to_string(summary_fpath).unwrap();
+    let rows = summary.split_once('\n').unwrap().1;
+    std::fs::write(&doubled_fpath, format!("{}{}", summary, rows)).unwrap();
+    let doubled = compare_basecalls(&doubled_fpath, &selected, None);
+    std::fs::remove_file(&doubled_fpath).unwrap();
+    assert!(doubled.selected == 2 && doubled.control == 3 && doubled.missing == 1);
+    assert!(doubled.comparisons[2].control == length);
+}
+
+#[test]
+fn blow5_corrupt_record_skipped() {
+    let mut blow5 = std::fs::read("test_data/rand_reads_5.blow5").unwrap();
+    // zero the zlib header of the first record, after its u64 size
+    let header_size = u32::from_le_bytes(blow5[64..68].try_into().unwrap()) as usize;
+    let records_start = 68 + header_size;
+    blow5[records_start + 8..records_start + 10].fill(0);
+    let blow5_fpath = std::env::temp_dir().join(format!("bad_reads_corrupt_{}.blow5", std::process::id()));
+    std::fs::write(&blow5_fpath, &blow5).unwrap();
+    let read_metas = scan_read_meta(&blow5_fpath, &ScanOptions::default());
+    
+    // cut the last record short, past the EOF marker
+    std::fs::write(&blow5_fpath, &blow5[..blow5.len() - 20]).unwrap();
+    let truncated = scan_read_meta(&blow5_fpath, &ScanOptions::default());
+    std::fs::remove_file(&blow5_fpath).unwrap();
+    
+    let intact = scan_read_meta(Path::new("test_data/rand_reads_5.blow5"), &ScanOptions::default());
+    assert!(read_metas.len() == 4);
+    assert!(read_metas.iter().map(|meta| &meta.read_id).eq(intact[1..].iter().map(|meta| &meta.read_id)));
+    assert!(truncated.len() == 3 && truncated[..] == read_metas[..3]);
+}